@@ -0,0 +1,21 @@
+use std::process::Command;
+
+fn main() {
+    // Embed the commit so that --version can say exactly what was built.
+    // Builds outside a git checkout, e.g., from a crates.io tarball, just
+    // don't get one.
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success());
+    if let Some(output) = output {
+        let hash = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=CHMI_COMMIT_HASH={}", hash.trim());
+
+        // Rebuild when a commit is checked out or made. Outside a checkout,
+        // the paths don't exist and would force a rebuild every time.
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs");
+    }
+}
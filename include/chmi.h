@@ -0,0 +1,85 @@
+#ifndef CHMI_H
+#define CHMI_H
+
+/* Generated by cbindgen from src/ffi.rs. Don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded.
+ */
+#define CHMI_OK 0
+
+/**
+ * A pointer argument was null, or a string argument wasn't UTF-8.
+ */
+#define CHMI_ERROR_INVALID_ARGUMENT 1
+
+/**
+ * No monitor has the device ID.
+ */
+#define CHMI_ERROR_NOT_FOUND 2
+
+/**
+ * Getting the monitors or talking to one failed, e.g., because the monitor
+ * didn't respond or doesn't list the input.
+ */
+#define CHMI_ERROR_FAILED 3
+
+/**
+ * A monitor listed by `chmi_list_displays`.
+ */
+typedef struct ChmiDisplay {
+  /**
+   * The device ID, which identifies the monitor in the other functions.
+   */
+  char *id;
+  /**
+   * The friendly name, e.g., "DELL U2720Q".
+   */
+  char *name;
+} ChmiDisplay;
+
+/**
+ * Lists the monitors that respond to DDC/CI. On success, `*displays` points
+ * to `*count` monitors, which must be freed with `chmi_free_displays`.
+ *
+ * # Safety
+ * `displays` and `count` must be null or valid for writes.
+ */
+int32_t chmi_list_displays(ChmiDisplay **displays, size_t *count);
+
+/**
+ * Frees the monitors listed by `chmi_list_displays`. Does nothing if
+ * `displays` is null.
+ *
+ * # Safety
+ * `displays` and `count` must be from the same `chmi_list_displays` call,
+ * and the monitors must not be used afterwards.
+ */
+void chmi_free_displays(ChmiDisplay *displays, size_t count);
+
+/**
+ * Gets the raw input select value of the current input of the monitor with
+ * device ID `id`, e.g., 0x11 for HDMI 1.
+ *
+ * # Safety
+ * `id` must be null or a nul-terminated string, and `input` must be null or
+ * valid for writes.
+ */
+int32_t chmi_get_input(const char *id, uint8_t *input);
+
+/**
+ * Switches the monitor with device ID `id` to the input with the raw input
+ * select value `input`, and waits for the monitor to report it.
+ *
+ * # Safety
+ * `id` must be null or a nul-terminated string.
+ */
+int32_t chmi_set_input(const char *id, uint8_t input);
+
+#endif  /* CHMI_H */
@@ -1,12 +1,25 @@
-use std::fs;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{anyhow, Context};
-use directories::ProjectDirs;
-use rusqlite::Connection;
+use anyhow::Context;
+use rusqlite::{
+    Connection, ErrorCode, OptionalExtension, TransactionBehavior,
+};
+use tracing::{debug, warn};
 
-struct CapabilitiesCacheEntry {
-    device_id: String,
-    capabilities_string: String,
+/// The environment variable that overrides the cache directory.
+const CACHE_DIR_VAR: &str = "CHMI_CACHE_DIR";
+/// How long to wait for another chmi process to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the number of seconds since the Unix epoch.
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
 }
 
 pub struct CapabilitiesCache {
@@ -14,62 +27,365 @@ pub struct CapabilitiesCache {
 }
 
 impl CapabilitiesCache {
-    pub fn new() -> anyhow::Result<CapabilitiesCache> {
-        let project_dirs = ProjectDirs::from("", "", "chmi").ok_or(
-            anyhow!("failed to compute the cache directory location"),
-        )?;
+    /// Opens the cache in `cache_dir`, the `CHMI_CACHE_DIR` directory, or the
+    /// default cache directory, in that order.
+    ///
+    /// If the default cache directory can't be used, e.g., because it isn't
+    /// writable, the cache is kept in memory instead.
+    pub fn new(cache_dir: Option<&Path>) -> anyhow::Result<CapabilitiesCache> {
+        let cache_dir = cache_dir
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os(CACHE_DIR_VAR).map(PathBuf::from));
+        if let Some(cache_dir) = cache_dir {
+            return CapabilitiesCache::open_in(&cache_dir);
+        }
+
+        let result = crate::project_dirs().and_then(|project_dirs| {
+            CapabilitiesCache::open_in(project_dirs.cache_dir())
+        });
+        match result {
+            Ok(cache) => Ok(cache),
+            Err(err) => {
+                debug!("using an in-memory capabilities cache: {:#}", err);
+                let connection = Connection::open_in_memory().context(
+                    "failed to open an in-memory capabilities database",
+                )?;
+                CapabilitiesCache::from_connection(connection)
+            }
+        }
+    }
 
-        fs::create_dir_all(project_dirs.cache_dir())
+    /// Opens the cache in `cache_dir`, creating the directory if needed.
+    fn open_in(cache_dir: &Path) -> anyhow::Result<CapabilitiesCache> {
+        fs::create_dir_all(cache_dir)
             .context("failed to create the cache directory")?;
 
-        let cache_path = project_dirs.cache_dir().join("capabilities.db");
-        let connection = Connection::open(cache_path).context(
-            "failed to open a connection to the capabilities database",
-        )?;
+        let cache_path = cache_dir.join("capabilities.db");
+        debug!("using the capabilities cache at '{}'", cache_path.display());
+        CapabilitiesCache::open(&cache_path)
+    }
 
+    /// Opens the cache at `path`, recreating it if it's corrupt. It's only a
+    /// cache, so losing its entries is fine.
+    fn open(path: &Path) -> anyhow::Result<CapabilitiesCache> {
+        let open = || {
+            let connection = Connection::open(path).context(
+                "failed to open a connection to the capabilities database",
+            )?;
+            CapabilitiesCache::from_connection(connection)
+        };
+
+        match open() {
+            Err(err) if is_corrupt(&err) => {
+                warn!("recreating the capabilities cache: {:#}", err);
+                fs::remove_file(path)
+                    .context("failed to remove the capabilities database")?;
+                open()
+            }
+            result => result,
+        }
+    }
+
+    fn from_connection(
+        mut connection: Connection,
+    ) -> anyhow::Result<CapabilitiesCache> {
         connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .context("failed to set the capabilities database busy timeout")?;
+
+        // In WAL mode, readers don't block writers and vice versa, so
+        // concurrent chmi processes wait on each other less, and an
+        // interrupted write can't leave a half-written database behind.
+        // In-memory databases stay in memory mode.
+        let journal_mode: String = connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| {
+                row.get(0)
+            })
+            .context("failed to set the capabilities database journal mode")?;
+        debug!("using the '{}' journal mode for the cache", journal_mode);
+
+        // Take the write lock up front so that concurrent processes
+        // migrating the same database wait for each other instead of
+        // failing, and so that an interrupted migration is rolled back.
+        let transaction = connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("failed to start migrating the capabilities database")?;
+
+        transaction
             .execute(
                 "CREATE TABLE IF NOT EXISTS capabilities (
                 id                  INTEGER PRIMARY KEY,
                 device_id           TEXT NOT NULL,
-                capabilities_string TEXT NOT NULL
+                capabilities_string TEXT NOT NULL,
+                created_at          INTEGER NOT NULL DEFAULT 0,
+                length              INTEGER NOT NULL DEFAULT 0
             )",
                 (),
             )
             .context("failed to create the capabilities table")?;
 
+        // Older versions of the cache didn't record when entries were
+        // created or how long the monitor said its capabilities string was.
+        // Add the columns with a default of 0 so that their entries are
+        // treated as expired and mismatched.
+        add_column_if_missing(&transaction, "created_at")?;
+        add_column_if_missing(&transaction, "length")?;
+
+        // Older versions of the cache allowed duplicate device IDs. Keep the
+        // most recent entry for each device ID before enforcing uniqueness.
+        transaction
+            .execute_batch(
+                "DELETE FROM capabilities WHERE id NOT IN (
+                SELECT MAX(id) FROM capabilities GROUP BY device_id
+            );
+            CREATE UNIQUE INDEX IF NOT EXISTS capabilities_device_id
+                ON capabilities (device_id);",
+            )
+            .context("failed to create the capabilities index")?;
+
+        transaction
+            .commit()
+            .context("failed to migrate the capabilities database")?;
+
         Ok(CapabilitiesCache { connection })
     }
 
-    pub fn get(&self, device_id: &str) -> anyhow::Result<Option<String>> {
-        let mut statement = self.connection.prepare(
-            "SELECT device_id, capabilities_string FROM capabilities",
+    /// Returns the cached capabilities string for a device, ignoring entries
+    /// older than `max_age`.
+    ///
+    /// `length` is the capabilities string length the monitor reports. The
+    /// entry is ignored if it was cached with a different length, since
+    /// Windows can reuse a device ID when a different monitor is connected
+    /// to the same port.
+    pub fn get(
+        &self,
+        device_id: &str,
+        length: u32,
+        max_age: Duration,
+    ) -> anyhow::Result<Option<String>> {
+        let oldest = unix_time().saturating_sub(max_age.as_secs() as i64);
+        self.connection.execute(
+            "DELETE FROM capabilities WHERE created_at < ?1",
+            (oldest,),
         )?;
 
-        let entries = statement.query_map((), |row| {
-            Ok(CapabilitiesCacheEntry {
-                device_id: row.get(0)?,
-                capabilities_string: row.get(1)?,
-            })
-        })?;
+        let capabilities_string = self
+            .connection
+            .query_row(
+                "SELECT capabilities_string FROM capabilities WHERE device_id = ?1 AND length = ?2 LIMIT 1",
+                (device_id, length),
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(capabilities_string)
+    }
+
+    /// Removes the cached capabilities string for a device.
+    pub fn invalidate(&self, device_id: &str) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM capabilities WHERE device_id = ?1",
+            (device_id,),
+        )?;
+
+        Ok(())
+    }
 
-        let entry = entries
-            .filter_map(|entry| entry.ok())
-            .find(|entry| entry.device_id == device_id)
-            .map(|entry| entry.capabilities_string);
+    /// Removes all cached capabilities strings.
+    pub fn clear(&self) -> anyhow::Result<()> {
+        self.connection.execute("DELETE FROM capabilities", ())?;
 
-        Ok(entry)
+        Ok(())
     }
 
+    /// Caches the capabilities string for a device, replacing any cached one.
+    /// See [`CapabilitiesCache::get`] for `length`.
     pub fn set(
         &self,
         device_id: &str,
+        length: u32,
         capabilities_string: &str,
     ) -> anyhow::Result<()> {
         self.connection.execute(
-            "INSERT INTO capabilities (device_id, capabilities_string) VALUES (?1, ?2)",
-            (device_id, capabilities_string))?;
+            "INSERT OR REPLACE INTO capabilities (device_id, capabilities_string, created_at, length) VALUES (?1, ?2, ?3, ?4)",
+            (device_id, capabilities_string, unix_time(), length))?;
 
         Ok(())
     }
 }
+
+/// Adds the integer column `name`, with a default of 0, to the capabilities
+/// table if it doesn't have it.
+fn add_column_if_missing(
+    connection: &Connection,
+    name: &str,
+) -> anyhow::Result<()> {
+    let has_column = connection
+        .prepare(
+            "SELECT 1 FROM pragma_table_info('capabilities') WHERE name = ?1",
+        )?
+        .exists((name,))?;
+    if !has_column {
+        connection
+            .execute(
+                &format!(
+                    "ALTER TABLE capabilities ADD COLUMN {} INTEGER NOT NULL DEFAULT 0",
+                    name
+                ),
+                (),
+            )
+            .context("failed to migrate the capabilities table")?;
+    }
+
+    Ok(())
+}
+
+/// Returns whether `err` is caused by a corrupt database file.
+fn is_corrupt(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<rusqlite::Error>().is_some_and(|err| {
+        matches!(
+            err.sqlite_error_code(),
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_ID: &str = "device";
+    const CAPABILITIES_STRING: &str = "(vcp(60(11 12 0F)))";
+    const LENGTH: u32 = CAPABILITIES_STRING.len() as u32 + 1;
+    const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn migrate_cache_without_created_at() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE capabilities (
+                id                  INTEGER PRIMARY KEY,
+                device_id           TEXT NOT NULL,
+                capabilities_string TEXT NOT NULL
+            )",
+                (),
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO capabilities (device_id, capabilities_string) VALUES (?1, ?2)",
+                (DEVICE_ID, CAPABILITIES_STRING),
+            )
+            .unwrap();
+
+        let cache = CapabilitiesCache::from_connection(connection).unwrap();
+        assert_eq!(cache.get(DEVICE_ID, LENGTH, MAX_AGE).unwrap(), None);
+
+        cache.set(DEVICE_ID, LENGTH, CAPABILITIES_STRING).unwrap();
+        assert_eq!(
+            cache.get(DEVICE_ID, LENGTH, MAX_AGE).unwrap().as_deref(),
+            Some(CAPABILITIES_STRING)
+        );
+    }
+
+    /// Returns the path of a database file for a test, removing any file
+    /// left over from a previous run.
+    fn test_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "chmi-{}-{}.db",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn recreate_corrupt_cache() {
+        let path = test_path("corrupt");
+        fs::write(&path, "not a database, just some garbage bytes").unwrap();
+
+        let cache = CapabilitiesCache::open(&path).unwrap();
+        cache.set(DEVICE_ID, LENGTH, CAPABILITIES_STRING).unwrap();
+        assert_eq!(
+            cache.get(DEVICE_ID, LENGTH, MAX_AGE).unwrap().as_deref(),
+            Some(CAPABILITIES_STRING)
+        );
+
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_cache_concurrently() {
+        let path = test_path("concurrent");
+
+        std::thread::scope(|scope| {
+            for i in 0..2 {
+                let path = &path;
+                scope.spawn(move || {
+                    let cache = CapabilitiesCache::open(path).unwrap();
+                    for j in 0..20 {
+                        let device_id = format!("device-{}-{}", i, j);
+                        cache
+                            .set(&device_id, LENGTH, CAPABILITIES_STRING)
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let cache = CapabilitiesCache::open(&path).unwrap();
+        for i in 0..2 {
+            for j in 0..20 {
+                let device_id = format!("device-{}-{}", i, j);
+                assert_eq!(
+                    cache.get(&device_id, LENGTH, MAX_AGE).unwrap().as_deref(),
+                    Some(CAPABILITIES_STRING)
+                );
+            }
+        }
+
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_cache_in_wal_mode() {
+        let path = test_path("wal");
+
+        let cache = CapabilitiesCache::open(&path).unwrap();
+        let journal_mode: String = cache
+            .connection
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        drop(cache);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn get_ignores_entries_with_another_length() {
+        let connection = Connection::open_in_memory().unwrap();
+        let cache = CapabilitiesCache::from_connection(connection).unwrap();
+
+        cache.set(DEVICE_ID, LENGTH, CAPABILITIES_STRING).unwrap();
+        assert_eq!(cache.get(DEVICE_ID, LENGTH + 1, MAX_AGE).unwrap(), None);
+    }
+
+    #[test]
+    fn get_ignores_expired_entries() {
+        let connection = Connection::open_in_memory().unwrap();
+        let cache = CapabilitiesCache::from_connection(connection).unwrap();
+
+        cache.set(DEVICE_ID, LENGTH, CAPABILITIES_STRING).unwrap();
+        cache
+            .connection
+            .execute("UPDATE capabilities SET created_at = 0", ())
+            .unwrap();
+
+        assert_eq!(cache.get(DEVICE_ID, LENGTH, MAX_AGE).unwrap(), None);
+    }
+}
@@ -1,82 +1,763 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+use serde::{Serialize, Serializer};
 
 pub const INPUT_SELECT_CODE: u8 = 0x60;
+/// The VCP code for the luminance, i.e., brightness, of a monitor.
+pub const BRIGHTNESS_CODE: u8 = 0x10;
+/// The VCP code for the contrast of a monitor.
+pub const CONTRAST_CODE: u8 = 0x12;
+/// The VCP code for the power mode of a monitor.
+pub const POWER_MODE_CODE: u8 = 0xD6;
+/// The DDC/CI command opcode for setting the value of a VCP code.
+pub const SET_VCP_COMMAND: u8 = 0x03;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct VcpCode {
+    #[serde(serialize_with = "serialize_hex")]
     pub code: u8,
+    #[serde(serialize_with = "serialize_hex_seq")]
     pub values: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl VcpCode {
+    /// Returns whether the code takes a value in a range, e.g., brightness,
+    /// rather than one of a list, e.g., input select.
+    ///
+    /// Capabilities strings only list the values of non-continuous codes,
+    /// so a code without values is assumed to be continuous.
+    pub fn is_continuous(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// The parsed capabilities of a monitor.
+///
+/// When serialized, codes and values are hex strings, e.g., "0x60", like in
+/// capabilities strings.
+#[derive(Debug, Default, Serialize)]
 pub struct Capabilities {
     pub vcp: Option<Vec<VcpCode>>,
+    pub model: Option<String>,
+    #[serde(serialize_with = "serialize_hex_seq")]
+    pub commands: Vec<u8>,
+    /// The protocol class from the `prot` group, e.g., "monitor".
+    pub protocol: Option<String>,
+    /// The display technology from the `type` group in lowercase, e.g.,
+    /// "lcd" or "crt".
+    pub display_type: Option<String>,
+    /// The MCCS version the monitor implements from the `mccs_ver` group,
+    /// e.g., "2.1".
+    pub mccs_version: Option<String>,
+    /// The labels some monitors give their inputs in the capabilities
+    /// string, e.g., "PC", by raw input value. These are usually what the
+    /// monitor shows in its OSD.
+    #[serde(serialize_with = "serialize_hex_keys")]
+    pub input_labels: BTreeMap<u8, String>,
+}
+
+/// Formats a code or value as a hex string, e.g., "0x60".
+fn hex(value: u8) -> String {
+    format!("{:#04X}", value)
+}
+
+fn serialize_hex<S: Serializer>(
+    value: &u8,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex(*value))
+}
+
+fn serialize_hex_seq<S: Serializer>(
+    values: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|&value| hex(value)))
+}
+
+fn serialize_hex_keys<S: Serializer>(
+    map: &BTreeMap<u8, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(&key, value)| (hex(key), value)))
 }
 
+/// An input of a monitor. The values and names of the inputs are in
+/// [`INPUTS`].
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Input {
+    Vga1,
+    Vga2,
+    Dvi1,
+    Dvi2,
+    Composite1,
+    Composite2,
+    SVideo1,
+    SVideo2,
+    Tuner1,
+    Tuner2,
+    Tuner3,
+    Component1,
+    Component2,
+    Component3,
     DisplayPort1,
     DisplayPort2,
     Hdmi1,
     Hdmi2,
+    UsbC,
+    /// An input chmi doesn't know, e.g., a vendor-specific one, with its raw
+    /// value.
+    Other(u8),
+}
+
+impl Input {
+    /// The inputs chmi knows, i.e., every input but [`Input::Other`], in the
+    /// order of [`INPUTS`].
+    pub const ALL: [Input; INPUTS.len()] = {
+        let mut all = [Input::Other(0); INPUTS.len()];
+        let mut i = 0;
+        while i < INPUTS.len() {
+            all[i] = INPUTS[i].0;
+            i += 1;
+        }
+        all
+    };
+
+    /// Returns the short names for the input, other than its name.
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Input::DisplayPort1 => &["dp1"],
+            Input::DisplayPort2 => &["dp2"],
+            _ => &[],
+        }
+    }
+
+    /// Returns the input in `inputs` named `s`.
+    ///
+    /// Names are matched ignoring case and non-alphanumeric characters, and
+    /// can be aliases, e.g., "dp1", or abbreviations, e.g., "dp" if `inputs`
+    /// has only one DisplayPort input.
+    ///
+    /// # Errors
+    /// Returns `Err` if no input or multiple inputs match `s`.
+    pub fn find(s: &str, inputs: &[Input]) -> anyhow::Result<Input> {
+        let name = normalize(s);
+        if name.is_empty() {
+            bail!("no input matches '{}'", s);
+        }
+
+        let names = |input: &Input| {
+            let mut names = vec![normalize(&input.to_string())];
+            names
+                .extend(input.aliases().iter().map(|alias| alias.to_string()));
+            names
+        };
+
+        if let Some(&input) =
+            inputs.iter().find(|input| names(input).contains(&name))
+        {
+            return Ok(input);
+        }
+
+        let candidates: Vec<Input> = inputs
+            .iter()
+            .copied()
+            .filter(|input| names(input).iter().any(|n| n.starts_with(&name)))
+            .collect();
+        match candidates.as_slice() {
+            [] => bail!("no input matches '{}'", s),
+            [input] => Ok(*input),
+            _ => bail!(
+                "'{}' is ambiguous, it could be {}",
+                s,
+                candidates
+                    .iter()
+                    .map(|input| input.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Returns the input select value in a VCP reply.
+///
+/// MCCS puts the value in the low byte, but some monitors, e.g., some Samsung
+/// ones, put it in the high byte instead and report 0x1100 for HDMI 1. To
+/// work around them, the high byte is used if the low byte isn't a known
+/// input but the high byte is.
+pub fn input_value(vcp_value: u16) -> u8 {
+    let [high, low] = vcp_value.to_be_bytes();
+    let is_known = |value| !matches!(Input::from(value), Input::Other(_));
+    if !is_known(low) && is_known(high) {
+        high
+    } else {
+        low
+    }
+}
+
+/// VCP codes whose values are two's complement signed numbers. These are the
+/// color temperature adjustments, 0x0B (color temperature increment) and
+/// 0x0C (color temperature request), which some monitors report as signed
+/// offsets from their preset.
+const SIGNED_VCP_CODES: [u8; 2] = [0x0B, 0x0C];
+
+/// Returns whether the values of VCP code `code` are signed.
+pub fn is_signed_vcp_code(code: u8) -> bool {
+    SIGNED_VCP_CODES.contains(&code)
+}
+
+/// Returns a value of VCP code `code` as a signed number.
+///
+/// Values of signed codes are reinterpreted as two's complement, e.g., 0xFFFF
+/// is -1. Values of other codes are unsigned, so they're `None` if they're
+/// above `i16::MAX`.
+pub fn signed_vcp_value(code: u8, value: u16) -> Option<i16> {
+    if is_signed_vcp_code(code) {
+        Some(value as i16)
+    } else {
+        i16::try_from(value).ok()
+    }
 }
 
+/// Formats the input with its name from [`INPUTS`], or its raw value if it
+/// isn't there.
 impl fmt::Display for Input {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self {
-            Input::DisplayPort1 => write!(f, "DisplayPort 1"),
-            Input::DisplayPort2 => write!(f, "DisplayPort 2"),
-            Input::Hdmi1 => write!(f, "HDMI 1"),
-            Input::Hdmi2 => write!(f, "HDMI 2"),
+        let value = u8::from(*self);
+        match input_name(value) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Input {:#04X}", value),
         }
     }
 }
 
+/// Serializes the input as a string, its variant name, e.g., "Hdmi1", or
+/// "Other(0x1B)" with the raw value for [`Input::Other`], so that every input
+/// has the same JSON type.
+impl Serialize for Input {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            Input::Other(value) => {
+                serializer.collect_str(&format_args!("Other({})", hex(*value)))
+            }
+            input => serializer.collect_str(&format_args!("{:?}", input)),
+        }
+    }
+}
+
+/// Parses an input from its name, ignoring case and non-alphanumeric
+/// characters, e.g., "HDMI 1", "hdmi1", and "Hdmi1" are all HDMI 1. See
+/// [`Input::find`].
+impl FromStr for Input {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Input::find(s, &Input::ALL)
+    }
+}
+
 impl From<Input> for u8 {
     fn from(value: Input) -> Self {
         match value {
-            Input::DisplayPort1 => 0x0F,
-            Input::DisplayPort2 => 0x10,
-            Input::Hdmi1 => 0x11,
-            Input::Hdmi2 => 0x12,
+            Input::Other(value) => value,
+            input => INPUTS
+                .iter()
+                .find(|&&(known, _, _)| known == input)
+                .map(|&(_, value, _)| value)
+                .expect("every input but Other is in the input table"),
         }
     }
 }
 
-impl TryFrom<u8> for Input {
-    type Error = ();
+impl From<u8> for Input {
+    fn from(value: u8) -> Self {
+        INPUTS
+            .iter()
+            .find(|&&(_, known, _)| known == value)
+            .map_or(Input::Other(value), |&(input, _, _)| input)
+    }
+}
+
+/// The values of the input select VCP code, 0x60, that chmi knows, with their
+/// inputs and names. The values up to 0x12 are defined by MCCS, and the rest
+/// are vendor-specific ones that are common enough to name.
+pub const INPUTS: [(Input, u8, &str); 19] = [
+    (Input::Vga1, 0x01, "VGA 1"),
+    (Input::Vga2, 0x02, "VGA 2"),
+    (Input::Dvi1, 0x03, "DVI 1"),
+    (Input::Dvi2, 0x04, "DVI 2"),
+    (Input::Composite1, 0x05, "Composite 1"),
+    (Input::Composite2, 0x06, "Composite 2"),
+    (Input::SVideo1, 0x07, "S-Video 1"),
+    (Input::SVideo2, 0x08, "S-Video 2"),
+    (Input::Tuner1, 0x09, "Tuner 1"),
+    (Input::Tuner2, 0x0A, "Tuner 2"),
+    (Input::Tuner3, 0x0B, "Tuner 3"),
+    (Input::Component1, 0x0C, "Component 1"),
+    (Input::Component2, 0x0D, "Component 2"),
+    (Input::Component3, 0x0E, "Component 3"),
+    (Input::DisplayPort1, 0x0F, "DisplayPort 1"),
+    (Input::DisplayPort2, 0x10, "DisplayPort 2"),
+    (Input::Hdmi1, 0x11, "HDMI 1"),
+    (Input::Hdmi2, 0x12, "HDMI 2"),
+    // USB-C isn't defined by MCCS, but several vendors (e.g., Dell and LG)
+    // use 0x1B for it.
+    (Input::UsbC, 0x1B, "USB-C"),
+];
+
+/// Returns the name of an input select value from [`INPUTS`], or `None` if
+/// chmi doesn't know it.
+pub fn input_name(value: u8) -> Option<&'static str> {
+    INPUTS
+        .iter()
+        .find(|&&(_, known, _)| known == value)
+        .map(|&(_, _, name)| name)
+}
+
+/// The type of a VCP code, as reported by the monitor when reading it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VcpType {
+    /// The code triggers an action when set, e.g., a factory reset, and its
+    /// value doesn't persist.
+    Momentary,
+    /// The code holds a value that can be read and set, e.g., brightness.
+    SetParameter,
+}
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
+impl VcpType {
+    /// Returns the type for the `MC_VCP_CODE_TYPE` value that the Monitor
+    /// Configuration API reports, where 0 is momentary and 1 is set
+    /// parameter. Unknown values are treated as set parameter, since most
+    /// codes are.
+    pub fn from_raw(value: i32) -> VcpType {
         match value {
-            0x0F => Ok(Input::DisplayPort1),
-            0x10 => Ok(Input::DisplayPort2),
-            0x11 => Ok(Input::Hdmi1),
-            0x12 => Ok(Input::Hdmi2),
-            _ => Err(()),
+            0 => VcpType::Momentary,
+            _ => VcpType::SetParameter,
+        }
+    }
+}
+
+impl fmt::Display for VcpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            VcpType::Momentary => write!(f, "momentary"),
+            VcpType::SetParameter => write!(f, "set parameter"),
+        }
+    }
+}
+
+/// A value of the power mode VCP code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerMode {
+    On,
+    // MCCS calls 0x04 "off (soft)", but most monitors treat it as standby.
+    Standby,
+    Off,
+}
+
+impl fmt::Display for PowerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            PowerMode::On => write!(f, "on"),
+            PowerMode::Standby => write!(f, "standby"),
+            PowerMode::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl FromStr for PowerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "on" => Ok(PowerMode::On),
+            "standby" => Ok(PowerMode::Standby),
+            "off" => Ok(PowerMode::Off),
+            _ => Err(anyhow!("unknown power mode '{}'", s)),
+        }
+    }
+}
+
+impl From<PowerMode> for u8 {
+    fn from(value: PowerMode) -> Self {
+        match value {
+            PowerMode::On => 0x01,
+            PowerMode::Standby => 0x04,
+            PowerMode::Off => 0x05,
         }
     }
 }
 
 impl Capabilities {
     pub fn has_input_select(&self) -> bool {
+        self.has_vcp_code(INPUT_SELECT_CODE)
+    }
+
+    /// Returns whether the monitor lists `code` in its VCP codes.
+    pub fn has_vcp_code(&self, code: u8) -> bool {
         self.vcp.as_ref().is_some_and(|vcp_codes| {
-            vcp_codes.iter().any(|vcp_code| vcp_code.code == INPUT_SELECT_CODE)
+            vcp_codes.iter().any(|vcp_code| vcp_code.code == code)
         })
     }
 
-    pub fn inputs(&self) -> Option<Vec<Input>> {
-        let mut inputs = Vec::new();
+    /// Returns whether the monitor supports setting VCP codes.
+    ///
+    /// Monitors that don't list their supported commands are assumed to
+    /// support it.
+    pub fn supports_set_vcp(&self) -> bool {
+        self.commands.is_empty() || self.commands.contains(&SET_VCP_COMMAND)
+    }
 
+    /// Returns the values the monitor lists for `code`, or `None` if it
+    /// doesn't list `code`.
+    pub fn values_for(&self, code: u8) -> Option<&[u8]> {
         let vcp_codes = self.vcp.as_ref()?;
-        let vcp_code = vcp_codes
-            .iter()
-            .find(|vcp_code| vcp_code.code == INPUT_SELECT_CODE)?;
-        for value in &vcp_code.values {
-            if let Ok(input) = (*value).try_into() {
-                inputs.push(input);
+        let vcp_code =
+            vcp_codes.iter().find(|vcp_code| vcp_code.code == code)?;
+        Some(&vcp_code.values)
+    }
+
+    /// Returns the inputs the monitor lists, including ones chmi doesn't
+    /// know as [`Input::Other`], or `None` if it doesn't support input select.
+    pub fn inputs(&self) -> Option<Vec<Input>> {
+        let values = self.values_for(INPUT_SELECT_CODE)?;
+        Some(values.iter().map(|&value| Input::from(value)).collect())
+    }
+
+    /// Returns the label the monitor gives `input`, if it has one.
+    pub fn input_label(&self, input: Input) -> Option<&str> {
+        self.input_labels.get(&u8::from(input)).map(|label| label.as_str())
+    }
+
+    /// Returns the name of `input` with the monitor's label for it, if it has
+    /// one, e.g., "HDMI 1 (PC)".
+    pub fn input_name(&self, input: Input) -> String {
+        match self.input_label(input) {
+            Some(label) => format!("{} ({})", input, label),
+            None => input.to_string(),
+        }
+    }
+
+    /// Returns the input the monitor lists named `s`.
+    ///
+    /// The monitor's labels are matched first, ignoring case and
+    /// non-alphanumeric characters, and then the names [`Input::find`]
+    /// matches.
+    ///
+    /// # Errors
+    /// Returns `Err` if no input or multiple inputs match `s`.
+    pub fn find_input(&self, s: &str) -> anyhow::Result<Input> {
+        let inputs = self.inputs().unwrap_or_default();
+        let name = normalize(s);
+        if !name.is_empty() {
+            if let Some(&input) = inputs.iter().find(|&&input| {
+                self.input_label(input)
+                    .is_some_and(|label| normalize(label) == name)
+            }) {
+                return Ok(input);
             }
         }
+        Input::find(s, &inputs)
+    }
+
+    /// Replaces the inputs the monitor lists, adding input select if it
+    /// isn't listed.
+    pub fn set_inputs(&mut self, inputs: &[Input]) {
+        let values: Vec<u8> =
+            inputs.iter().map(|&input| u8::from(input)).collect();
+        let vcp_codes = self.vcp.get_or_insert_with(Vec::new);
+        match vcp_codes
+            .iter_mut()
+            .find(|vcp_code| vcp_code.code == INPUT_SELECT_CODE)
+        {
+            Some(vcp_code) => vcp_code.values = values,
+            None => {
+                vcp_codes.push(VcpCode { code: INPUT_SELECT_CODE, values })
+            }
+        }
+    }
+}
+
+/// Lowercases `s` and removes non-alphanumeric characters for matching
+/// names.
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Returns the name of a well-known VCP code.
+fn vcp_code_name(code: u8) -> Option<&'static str> {
+    match code {
+        BRIGHTNESS_CODE => Some("luminance"),
+        CONTRAST_CODE => Some("contrast"),
+        INPUT_SELECT_CODE => Some("input"),
+        POWER_MODE_CODE => Some("power"),
+        _ => None,
+    }
+}
+
+/// Formats the capabilities for people, e.g., for bug reports, with one VCP
+/// code per line and codes and values in hex.
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "model: {}", self.model.as_deref().unwrap_or("unknown"))?;
+
+        write!(f, "\ncommands:")?;
+        if self.commands.is_empty() {
+            write!(f, " none")?;
+        }
+        for command in &self.commands {
+            write!(f, " {:#04X}", command)?;
+        }
+
+        let Some(vcp_codes) = &self.vcp else {
+            return write!(f, "\nvcp codes: none");
+        };
+        write!(f, "\nvcp codes:")?;
+        for vcp_code in vcp_codes {
+            write!(f, "\n  {:#04X}", vcp_code.code)?;
+            if let Some(name) = vcp_code_name(vcp_code.code) {
+                write!(f, " {}", name)?;
+            }
+            if vcp_code.values.is_empty() {
+                continue;
+            }
+
+            let values: Vec<String> = vcp_code
+                .values
+                .iter()
+                .map(|&value| match Input::from(value) {
+                    Input::Other(_) => format!("{:#04X}", value),
+                    input if vcp_code.code == INPUT_SELECT_CODE => {
+                        format!("{:#04X} ({})", value, input)
+                    }
+                    _ => format!("{:#04X}", value),
+                })
+                .collect();
+            write!(f, ": {}", values.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_input_by_name_or_alias() {
+        let inputs = Input::ALL;
+        assert_eq!(Input::find("HDMI 1", &inputs).unwrap(), Input::Hdmi1);
+        assert_eq!(Input::find("hdmi2", &inputs).unwrap(), Input::Hdmi2);
+        assert_eq!(Input::find("DP1", &inputs).unwrap(), Input::DisplayPort1);
+        assert_eq!(
+            Input::find("displayport-2", &inputs).unwrap(),
+            Input::DisplayPort2
+        );
+        assert!(Input::find("hdmi3", &inputs).is_err());
+        assert!(Input::find("", &inputs).is_err());
+    }
+
+    #[test]
+    fn input_table_round_trips() {
+        for &(input, value, name) in &INPUTS {
+            assert_eq!(u8::from(input), value);
+            assert_eq!(Input::from(value), input);
+            assert_eq!(input.to_string(), name);
+            assert_eq!(input_name(value), Some(name));
+        }
+        assert_eq!(Input::ALL.len(), INPUTS.len());
+        assert_eq!(Input::from(0x1C), Input::Other(0x1C));
+        assert_eq!(Input::Other(0x1C).to_string(), "Input 0x1C");
+        assert_eq!(input_name(0x1C), None);
+    }
+
+    #[test]
+    fn find_input_by_abbreviation() {
+        let inputs = [Input::Hdmi1, Input::Hdmi2, Input::DisplayPort1];
+        assert_eq!(Input::find("dp", &inputs).unwrap(), Input::DisplayPort1);
+        assert_eq!(Input::find("usb", &Input::ALL).unwrap(), Input::UsbC);
+        assert!(Input::find("hdmi", &inputs).is_err());
+        assert!(Input::find("dp", &Input::ALL).is_err());
+    }
+
+    #[test]
+    fn display_capabilities() {
+        let capabilities = Capabilities {
+            vcp: Some(vec![
+                VcpCode { code: 0x10, values: vec![] },
+                VcpCode { code: 0x60, values: vec![0x0F, 0x11, 0x20] },
+                VcpCode { code: 0xE0, values: vec![0x00, 0x01] },
+            ]),
+            model: Some("U2720Q".to_owned()),
+            commands: vec![0x01, 0x03],
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            capabilities.to_string(),
+            "model: U2720Q\n\
+             commands: 0x01 0x03\n\
+             vcp codes:\n  \
+             0x10 luminance\n  \
+             0x60 input: 0x0F (DisplayPort 1), 0x11 (HDMI 1), 0x20\n  \
+             0xE0: 0x00, 0x01"
+        );
+    }
+
+    #[test]
+    fn serialize_capabilities() {
+        let capabilities = Capabilities {
+            vcp: Some(vec![
+                VcpCode { code: 0x10, values: vec![] },
+                VcpCode { code: 0x60, values: vec![0x0F, 0x11] },
+            ]),
+            model: Some("U2720Q".to_owned()),
+            commands: vec![0x01, 0x03],
+            input_labels: BTreeMap::from([(0x11, "PC".to_owned())]),
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&capabilities).unwrap(),
+            r#"{"vcp":[{"code":"0x10","values":[]},{"code":"0x60","values":["0x0F","0x11"]}],"model":"U2720Q","commands":["0x01","0x03"],"protocol":null,"display_type":null,"mccs_version":null,"input_labels":{"0x11":"PC"}}"#
+        );
+    }
+
+    #[test]
+    fn serialize_inputs_as_strings() {
+        assert_eq!(
+            serde_json::to_string(&[Input::Hdmi1, Input::Other(0x1C)])
+                .unwrap(),
+            r#"["Hdmi1","Other(0x1C)"]"#
+        );
+    }
+
+    #[test]
+    fn set_inputs_replaces_listed_inputs() {
+        let mut capabilities = Capabilities {
+            vcp: Some(vec![
+                VcpCode { code: 0x10, values: vec![] },
+                VcpCode { code: 0x60, values: vec![0x11] },
+            ]),
+            ..Capabilities::default()
+        };
+        capabilities.set_inputs(&[Input::Hdmi1, Input::Hdmi2]);
+        assert_eq!(
+            capabilities.inputs(),
+            Some(vec![Input::Hdmi1, Input::Hdmi2])
+        );
+        assert!(capabilities.has_vcp_code(0x10));
+    }
+
+    #[test]
+    fn set_inputs_adds_input_select() {
+        let mut capabilities = Capabilities::default();
+        capabilities.set_inputs(&[Input::DisplayPort1]);
+        assert!(capabilities.has_input_select());
+        assert_eq!(capabilities.inputs(), Some(vec![Input::DisplayPort1]));
+    }
+
+    #[test]
+    fn find_input_by_label() {
+        let capabilities = Capabilities {
+            vcp: Some(vec![VcpCode {
+                code: 0x60,
+                values: vec![0x11, 0x12, 0x0F],
+            }]),
+            input_labels: BTreeMap::from([
+                (0x11, "PC".to_owned()),
+                (0x12, "Game Console".to_owned()),
+            ]),
+            ..Capabilities::default()
+        };
+        assert_eq!(capabilities.find_input("pc").unwrap(), Input::Hdmi1);
+        assert_eq!(
+            capabilities.find_input("game-console").unwrap(),
+            Input::Hdmi2
+        );
+        assert_eq!(
+            capabilities.find_input("dp").unwrap(),
+            Input::DisplayPort1
+        );
+        assert_eq!(capabilities.input_name(Input::Hdmi1), "HDMI 1 (PC)");
+        assert_eq!(
+            capabilities.input_name(Input::DisplayPort1),
+            "DisplayPort 1"
+        );
+        assert!(capabilities.find_input("laptop").is_err());
+    }
+
+    #[test]
+    fn display_capabilities_without_vcp_codes() {
+        let capabilities = Capabilities::default();
+        assert_eq!(
+            capabilities.to_string(),
+            "model: unknown\ncommands: none\nvcp codes: none"
+        );
+    }
+
+    #[test]
+    fn vcp_type_from_raw() {
+        assert_eq!(VcpType::from_raw(0), VcpType::Momentary);
+        assert_eq!(VcpType::from_raw(1), VcpType::SetParameter);
+        assert_eq!(VcpType::from_raw(2), VcpType::SetParameter);
+    }
+
+    #[test]
+    fn vcp_code_is_continuous_without_values() {
+        assert!(VcpCode { code: 0x10, values: vec![] }.is_continuous());
+        assert!(!VcpCode { code: 0x60, values: vec![0x11] }.is_continuous());
+    }
+
+    #[test]
+    fn signed_vcp_value_reinterprets_signed_codes() {
+        assert_eq!(signed_vcp_value(0x0B, 0x0000), Some(0));
+        assert_eq!(signed_vcp_value(0x0B, 0x7FFF), Some(i16::MAX));
+        assert_eq!(signed_vcp_value(0x0B, 0x8000), Some(i16::MIN));
+        assert_eq!(signed_vcp_value(0x0C, 0xFFFF), Some(-1));
+    }
+
+    #[test]
+    fn signed_vcp_value_keeps_unsigned_codes() {
+        assert_eq!(signed_vcp_value(0x10, 0x0000), Some(0));
+        assert_eq!(signed_vcp_value(0x10, 0x7FFF), Some(i16::MAX));
+        assert_eq!(signed_vcp_value(0x10, 0x8000), None);
+        assert_eq!(signed_vcp_value(0x10, 0xFFFF), None);
+    }
+
+    #[test]
+    fn input_round_trips_raw_values() {
+        for value in 0..=u8::MAX {
+            assert_eq!(u8::from(Input::from(value)), value);
+        }
+        assert_eq!(Input::from(0x11), Input::Hdmi1);
+        assert_eq!(Input::from(0x20), Input::Other(0x20));
+        assert_eq!(Input::Other(0x20).to_string(), "Input 0x20");
+    }
+
+    #[test]
+    fn input_value_uses_low_byte() {
+        assert_eq!(input_value(0x0011), 0x11);
+        assert_eq!(input_value(0x0F11), 0x11);
+    }
+
+    #[test]
+    fn input_value_falls_back_to_high_byte() {
+        assert_eq!(input_value(0x1100), 0x11);
+        assert_eq!(input_value(0x0F00), 0x0F);
+    }
 
-        Some(inputs)
+    #[test]
+    fn input_value_keeps_unknown_low_byte() {
+        assert_eq!(input_value(0x0020), 0x20);
+        assert_eq!(input_value(0x2020), 0x20);
     }
 }
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, fs, io};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::cap::Input;
+
+/// A name for a monitor and one of its inputs.
+#[derive(Debug, Deserialize)]
+pub struct Alias {
+    /// The monitor name or 1-based index.
+    pub display: String,
+    /// The input name, e.g., "Hdmi1" or "HDMI 1".
+    pub input: String,
+}
+
+/// Settings for a monitor that override its capabilities, for monitors
+/// whose capabilities leave out some of their inputs or that switch inputs
+/// with a non-standard VCP code.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Override {
+    /// The device ID of the monitor.
+    pub id: Option<String>,
+    /// The model in the monitor's capabilities, matched ignoring case.
+    pub model: Option<String>,
+    /// The input names, e.g., "HDMI 1", to offer instead of the ones in the
+    /// capabilities, if any.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// The VCP code to switch inputs with instead of input select, e.g.,
+    /// `0xF4`.
+    #[serde(default)]
+    pub input_select_code: Option<u8>,
+}
+
+impl Override {
+    /// Returns whether the override is for the monitor with the device ID
+    /// `id` and the model `model`.
+    pub fn matches(&self, id: &str, model: Option<&str>) -> bool {
+        let id_matches = self.id.as_ref().map(|self_id| self_id == id);
+        let model_matches = self.model.as_ref().map(|self_model| {
+            model.is_some_and(|model| model.eq_ignore_ascii_case(self_model))
+        });
+        match (id_matches, model_matches) {
+            (None, None) => false,
+            (id_matches, model_matches) => {
+                id_matches.unwrap_or(true) && model_matches.unwrap_or(true)
+            }
+        }
+    }
+
+    /// Returns the inputs named in the override.
+    ///
+    /// # Errors
+    /// Returns `Err` if a name doesn't match exactly one input.
+    pub fn inputs(&self) -> anyhow::Result<Vec<Input>> {
+        self.inputs.iter().map(|name| Input::find(name, &Input::ALL)).collect()
+    }
+}
+
+/// The user's configuration, read from `config.toml` in the config
+/// directory.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub aliases: HashMap<String, Alias>,
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<Override>,
+}
+
+impl Config {
+    /// Reads the config file, or returns the default config if there isn't
+    /// one.
+    pub fn load() -> anyhow::Result<Config> {
+        let config_path =
+            crate::project_dirs()?.config_dir().join("config.toml");
+
+        let contents = match fs::read_to_string(&config_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to read {}", config_path.display())
+                })
+            }
+        };
+
+        toml::from_str(&contents).with_context(|| {
+            format!("failed to parse {}", config_path.display())
+        })
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use crate::cap::{Capabilities, Input, INPUT_SELECT_CODE};
+
+/// Where a capabilities string came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CapabilitiesSource {
+    /// The capabilities cache.
+    Cache,
+    /// The monitor, over DDC/CI.
+    Monitor,
+}
+
+/// The outcome of one step of a diagnosis, with the error formatted for
+/// people since it's only reported.
+pub type Check<T> = Result<T, String>;
+
+/// What `chmi doctor` found out about a display monitor, i.e., a display on
+/// the desktop, and the physical monitors behind it.
+#[derive(Debug)]
+pub struct DisplayDiagnosis {
+    pub id: Check<String>,
+    pub name: Check<String>,
+    /// The number of physical monitors Windows reports for the display.
+    pub physical_monitor_count: Check<usize>,
+    /// The physical monitors, or the error if their handles couldn't be
+    /// obtained.
+    pub monitors: Check<Vec<MonitorDiagnosis>>,
+}
+
+/// What `chmi doctor` found out about a physical monitor.
+#[derive(Debug)]
+pub struct MonitorDiagnosis {
+    pub capabilities_string: Check<(String, CapabilitiesSource)>,
+    /// The parsed capabilities, or `None` if there's no capabilities string
+    /// to parse.
+    pub capabilities: Option<Check<Capabilities>>,
+    /// The raw value of the current input.
+    pub input: Check<u8>,
+}
+
+/// Formats the diagnosis as a report that can be pasted into a bug report.
+impl fmt::Display for DisplayDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Ok(name) => write!(f, "{}", name)?,
+            Err(err) => write!(f, "unknown name ({})", err)?,
+        }
+        match &self.id {
+            Ok(id) => write!(f, "\n  id: {}", id)?,
+            Err(err) => write!(f, "\n  id: failed, {}", err)?,
+        }
+
+        write!(f, "\n  physical monitors: ")?;
+        match self.physical_monitor_count {
+            Ok(0) => write!(
+                f,
+                "0, the display doesn't support DDC/CI, or it's turned off"
+            )?,
+            Ok(1) => write!(f, "1")?,
+            Ok(count) => write!(
+                f,
+                "{}, there might be a docking station, MST hub, or KVM switch between the monitors and this computer",
+                count
+            )?,
+            Err(ref err) => write!(f, "failed, {}", err)?,
+        }
+
+        let monitors = match &self.monitors {
+            Ok(monitors) => monitors,
+            Err(err) => {
+                return write!(
+                    f,
+                    "\n  physical monitor handles: failed, {}",
+                    err
+                )
+            }
+        };
+        for (i, monitor) in monitors.iter().enumerate() {
+            write!(f, "\n  monitor {}:", i + 1)?;
+            write!(f, "\n    handle: ok")?;
+            write!(f, "{}", monitor)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats the diagnosis as indented lines under a display's.
+impl fmt::Display for MonitorDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.capabilities_string {
+            Ok((capabilities_string, source)) => {
+                let source = match source {
+                    CapabilitiesSource::Cache => "cache",
+                    CapabilitiesSource::Monitor => "monitor",
+                };
+                write!(
+                    f,
+                    "\n    capabilities string (from the {}): {}",
+                    source, capabilities_string
+                )?;
+            }
+            Err(err) => {
+                write!(f, "\n    capabilities string: failed, {}", err)?
+            }
+        }
+
+        match &self.capabilities {
+            Some(Ok(capabilities)) => {
+                write!(f, "\n    capabilities: parsed")?;
+                match capabilities.inputs() {
+                    Some(inputs) => {
+                        let names: Vec<String> =
+                            inputs.iter().map(Input::to_string).collect();
+                        write!(
+                            f,
+                            "\n    input select ({:#04X}): yes, inputs: {}",
+                            INPUT_SELECT_CODE,
+                            if names.is_empty() {
+                                "none listed".to_owned()
+                            } else {
+                                names.join(", ")
+                            }
+                        )?;
+                    }
+                    None => write!(
+                        f,
+                        "\n    input select ({:#04X}): no",
+                        INPUT_SELECT_CODE
+                    )?,
+                }
+            }
+            Some(Err(err)) => {
+                write!(f, "\n    capabilities: failed, {}", err)?
+            }
+            None => write!(f, "\n    capabilities: skipped")?,
+        }
+
+        match self.input {
+            Ok(value) => write!(
+                f,
+                "\n    current input: {:#04X} ({})",
+                value,
+                Input::from(value)
+            ),
+            Err(ref err) => write!(f, "\n    current input: failed, {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cap::VcpCode;
+
+    #[test]
+    fn display_diagnosis() {
+        let diagnosis = DisplayDiagnosis {
+            id: Ok(r"\\?\DISPLAY#GSM5B08#1".to_owned()),
+            name: Ok("LG ULTRAGEAR".to_owned()),
+            physical_monitor_count: Ok(1),
+            monitors: Ok(vec![MonitorDiagnosis {
+                capabilities_string: Ok((
+                    "(vcp(60(11 0F)))".to_owned(),
+                    CapabilitiesSource::Cache,
+                )),
+                capabilities: Some(Ok(Capabilities {
+                    vcp: Some(vec![VcpCode {
+                        code: 0x60,
+                        values: vec![0x11, 0x0F],
+                    }]),
+                    ..Capabilities::default()
+                })),
+                input: Err("failed to retrieve the value".to_owned()),
+            }]),
+        };
+        assert_eq!(
+            diagnosis.to_string(),
+            r"LG ULTRAGEAR
+  id: \\?\DISPLAY#GSM5B08#1
+  physical monitors: 1
+  monitor 1:
+    handle: ok
+    capabilities string (from the cache): (vcp(60(11 0F)))
+    capabilities: parsed
+    input select (0x60): yes, inputs: HDMI 1, DisplayPort 1
+    current input: failed, failed to retrieve the value"
+        );
+    }
+
+    #[test]
+    fn display_diagnosis_without_physical_monitors() {
+        let diagnosis = DisplayDiagnosis {
+            id: Ok(r"\\?\DISPLAY#SHP14D0#1".to_owned()),
+            name: Err("unable to find the name".to_owned()),
+            physical_monitor_count: Ok(0),
+            monitors: Err(
+                "display monitor has no associated physical monitor"
+                    .to_owned(),
+            ),
+        };
+        assert_eq!(
+            diagnosis.to_string(),
+            r"unknown name (unable to find the name)
+  id: \\?\DISPLAY#SHP14D0#1
+  physical monitors: 0, the display doesn't support DDC/CI, or it's turned off
+  physical monitor handles: failed, display monitor has no associated physical monitor"
+        );
+    }
+}
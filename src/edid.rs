@@ -0,0 +1,223 @@
+/// The fixed pattern that starts every EDID base block.
+const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+/// The length of an EDID base block.
+const BLOCK_LEN: usize = 128;
+/// The offset of the manufacturer's PnP ID in the base block.
+const MANUFACTURER_OFFSET: usize = 8;
+/// The offset of the manufacturer's product code in the base block.
+const PRODUCT_CODE_OFFSET: usize = 10;
+/// The offset of the numeric serial number in the base block.
+const SERIAL_NUMBER_OFFSET: usize = 12;
+/// The offsets of the four display descriptors in the base block.
+const DESCRIPTOR_OFFSETS: [usize; 4] = [54, 72, 90, 108];
+/// The length of a display descriptor.
+const DESCRIPTOR_LEN: usize = 18;
+/// The tag of a display descriptor holding the serial number as text.
+const SERIAL_NUMBER_TAG: u8 = 0xFF;
+/// The tag of a display descriptor holding the model name as text.
+const PRODUCT_NAME_TAG: u8 = 0xFC;
+
+/// The details of a monitor from its EDID.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edid {
+    /// The three-letter PnP ID of the manufacturer, e.g., "DEL" for Dell.
+    pub manufacturer: String,
+    /// The manufacturer's code for the model.
+    pub product_code: u16,
+    /// The model name, e.g., "DELL U2720Q", if the EDID has one.
+    pub model: Option<String>,
+    /// The serial number, preferably the one that's usually printed on the
+    /// monitor.
+    pub serial: Option<String>,
+    /// The width and height in pixels of the preferred timing, which is the
+    /// native resolution of flat panels.
+    pub native_resolution: Option<(u16, u16)>,
+}
+
+impl Edid {
+    /// Decodes the base block of an EDID, or returns `None` if it isn't one.
+    pub fn parse(edid: &[u8]) -> Option<Edid> {
+        if edid.len() < BLOCK_LEN || edid[..HEADER.len()] != HEADER {
+            return None;
+        }
+
+        // The PnP ID is three letters packed into five bits each, with 1
+        // being 'A'.
+        let id = u16::from_be_bytes([
+            edid[MANUFACTURER_OFFSET],
+            edid[MANUFACTURER_OFFSET + 1],
+        ]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| match (id >> shift) & 0x1F {
+                letter @ 1..=26 => char::from(b'A' + letter as u8 - 1),
+                _ => '?',
+            })
+            .collect();
+
+        Some(Edid {
+            manufacturer,
+            product_code: u16::from_le_bytes([
+                edid[PRODUCT_CODE_OFFSET],
+                edid[PRODUCT_CODE_OFFSET + 1],
+            ]),
+            model: descriptor_text(edid, PRODUCT_NAME_TAG),
+            serial: serial_number(edid),
+            native_resolution: native_resolution(edid),
+        })
+    }
+}
+
+/// Returns the text of the first display descriptor with `tag`, if there's
+/// one and it isn't blank.
+fn descriptor_text(edid: &[u8], tag: u8) -> Option<String> {
+    DESCRIPTOR_OFFSETS.iter().find_map(|&offset| {
+        let descriptor = &edid[offset..offset + DESCRIPTOR_LEN];
+        // Display descriptors start with three zero bytes, unlike detailed
+        // timing descriptors.
+        if descriptor[..3] != [0, 0, 0] || descriptor[3] != tag {
+            return None;
+        }
+
+        // The text is terminated by a line feed and padded with spaces.
+        let text = &descriptor[5..];
+        let text = text.split(|&b| b == b'\n').next().unwrap_or(text);
+        let text = String::from_utf8_lossy(text).trim().to_owned();
+        (!text.is_empty()).then_some(text)
+    })
+}
+
+/// Returns the resolution of the preferred timing, which is always the first
+/// detailed timing descriptor.
+fn native_resolution(edid: &[u8]) -> Option<(u16, u16)> {
+    let descriptor = &edid[DESCRIPTOR_OFFSETS[0]..][..DESCRIPTOR_LEN];
+    // A zero pixel clock means it's a display descriptor instead.
+    if descriptor[..2] == [0, 0] {
+        return None;
+    }
+
+    // The upper four bits of each dimension are in the upper nibble of a
+    // shared byte.
+    let width = u16::from(descriptor[2]) | u16::from(descriptor[4] >> 4) << 8;
+    let height = u16::from(descriptor[5]) | u16::from(descriptor[7] >> 4) << 8;
+    Some((width, height))
+}
+
+/// Returns the serial number of the monitor an EDID belongs to.
+///
+/// The serial number string descriptor is preferred since it's usually what's
+/// printed on the monitor. Otherwise, the numeric serial number is used, if
+/// it's set.
+pub fn serial_number(edid: &[u8]) -> Option<String> {
+    if edid.len() < BLOCK_LEN || edid[..HEADER.len()] != HEADER {
+        return None;
+    }
+
+    if let Some(serial) = descriptor_text(edid, SERIAL_NUMBER_TAG) {
+        return Some(serial);
+    }
+
+    let serial = u32::from_le_bytes(
+        edid[SERIAL_NUMBER_OFFSET..SERIAL_NUMBER_OFFSET + 4]
+            .try_into()
+            .unwrap(),
+    );
+    (serial != 0).then(|| serial.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an EDID base block of a Dell monitor with a 1920x1080
+    /// preferred timing, a numeric serial number, and, if given, a serial
+    /// number string descriptor.
+    fn edid(numeric_serial: u32, serial: Option<&str>) -> Vec<u8> {
+        let mut edid = vec![0; BLOCK_LEN];
+        edid[..HEADER.len()].copy_from_slice(&HEADER);
+        edid[MANUFACTURER_OFFSET..MANUFACTURER_OFFSET + 2]
+            .copy_from_slice(&[0x10, 0xAC]);
+        edid[PRODUCT_CODE_OFFSET..PRODUCT_CODE_OFFSET + 2]
+            .copy_from_slice(&0xA0C4u16.to_le_bytes());
+        edid[SERIAL_NUMBER_OFFSET..SERIAL_NUMBER_OFFSET + 4]
+            .copy_from_slice(&numeric_serial.to_le_bytes());
+        // A detailed timing descriptor, which must be skipped when looking
+        // for display descriptors.
+        edid[54..62].copy_from_slice(&[
+            0x02, 0x3A, 0x80, 0x18, 0x71, 0x38, 0x2D, 0x40,
+        ]);
+        if let Some(serial) = serial {
+            set_descriptor_text(&mut edid, 72, SERIAL_NUMBER_TAG, serial);
+        }
+        edid
+    }
+
+    /// Writes a display descriptor with `tag` and `text` at `offset`.
+    fn set_descriptor_text(
+        edid: &mut [u8],
+        offset: usize,
+        tag: u8,
+        text: &str,
+    ) {
+        let descriptor = &mut edid[offset..offset + DESCRIPTOR_LEN];
+        descriptor[3] = tag;
+        let mut padded = [b' '; 13];
+        padded[..text.len()].copy_from_slice(text.as_bytes());
+        if text.len() < padded.len() {
+            padded[text.len()] = b'\n';
+        }
+        descriptor[5..].copy_from_slice(&padded);
+    }
+
+    #[test]
+    fn parse_edid() {
+        let mut edid = edid(16843009, Some("CN0ABC123"));
+        set_descriptor_text(&mut edid, 90, PRODUCT_NAME_TAG, "DELL U2720Q");
+        assert_eq!(
+            Edid::parse(&edid),
+            Some(Edid {
+                manufacturer: "DEL".to_owned(),
+                product_code: 0xA0C4,
+                model: Some("DELL U2720Q".to_owned()),
+                serial: Some("CN0ABC123".to_owned()),
+                native_resolution: Some((1920, 1080)),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_edid_without_optional_details() {
+        let mut edid = edid(0, None);
+        edid[54..62].fill(0);
+        let edid = Edid::parse(&edid).unwrap();
+        assert_eq!(edid.model, None);
+        assert_eq!(edid.serial, None);
+        assert_eq!(edid.native_resolution, None);
+        assert_eq!(Edid::parse(&[0; 16]), None);
+    }
+
+    #[test]
+    fn serial_number_prefers_string_descriptor() {
+        let edid = edid(16843009, Some("CN0ABC123"));
+        assert_eq!(serial_number(&edid).as_deref(), Some("CN0ABC123"));
+    }
+
+    #[test]
+    fn serial_number_falls_back_to_numeric_serial() {
+        let edid = edid(16843009, None);
+        assert_eq!(serial_number(&edid).as_deref(), Some("16843009"));
+    }
+
+    #[test]
+    fn serial_number_without_serial() {
+        assert_eq!(serial_number(&edid(0, None)), None);
+    }
+
+    #[test]
+    fn serial_number_of_invalid_edid() {
+        let mut edid = edid(16843009, Some("CN0ABC123"));
+        edid[0] = 0xFF;
+        assert_eq!(serial_number(&edid), None);
+        assert_eq!(serial_number(&[0; 16]), None);
+    }
+}
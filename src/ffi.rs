@@ -0,0 +1,220 @@
+//! A C interface for apps that embed chmi instead of running it, e.g., tray
+//! utilities. `include/chmi.h` declares it for C.
+//!
+//! Monitors are identified by their device ID, which is stable across calls
+//! unlike their index. Each call gets the monitors again, so it can be as
+//! slow as starting chmi.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+use tracing::error;
+
+use crate::{Input, Monitor, Options};
+
+/// The call succeeded.
+pub const CHMI_OK: i32 = 0;
+/// A pointer argument was null, or a string argument wasn't UTF-8.
+pub const CHMI_ERROR_INVALID_ARGUMENT: i32 = 1;
+/// No monitor has the device ID.
+pub const CHMI_ERROR_NOT_FOUND: i32 = 2;
+/// Getting the monitors or talking to one failed, e.g., because the monitor
+/// didn't respond or doesn't list the input.
+pub const CHMI_ERROR_FAILED: i32 = 3;
+
+/// A monitor listed by `chmi_list_displays`.
+#[repr(C)]
+pub struct ChmiDisplay {
+    /// The device ID, which identifies the monitor in the other functions.
+    pub id: *mut c_char,
+    /// The friendly name, e.g., "DELL U2720Q".
+    pub name: *mut c_char,
+}
+
+/// Lists the monitors that respond to DDC/CI. On success, `*displays` points
+/// to `*count` monitors, which must be freed with `chmi_free_displays`.
+///
+/// # Safety
+/// `displays` and `count` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn chmi_list_displays(
+    displays: *mut *mut ChmiDisplay,
+    count: *mut usize,
+) -> i32 {
+    if displays.is_null() || count.is_null() {
+        return CHMI_ERROR_INVALID_ARGUMENT;
+    }
+
+    let monitors = match crate::get_monitors(&Options::default()) {
+        Ok(monitors) => monitors,
+        Err(err) => {
+            error!("{:#}", err);
+            return CHMI_ERROR_FAILED;
+        }
+    };
+
+    // IDs and names come from wide strings that are cut at the first nul, so
+    // they can't contain one.
+    let list: Box<[ChmiDisplay]> = monitors
+        .iter()
+        .map(|monitor| ChmiDisplay {
+            id: CString::new(monitor.id()).unwrap_or_default().into_raw(),
+            name: CString::new(monitor.name()).unwrap_or_default().into_raw(),
+        })
+        .collect();
+    *count = list.len();
+    *displays = Box::into_raw(list).cast();
+    CHMI_OK
+}
+
+/// Frees the monitors listed by `chmi_list_displays`. Does nothing if
+/// `displays` is null.
+///
+/// # Safety
+/// `displays` and `count` must be from the same `chmi_list_displays` call,
+/// and the monitors must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chmi_free_displays(
+    displays: *mut ChmiDisplay,
+    count: usize,
+) {
+    if displays.is_null() {
+        return;
+    }
+
+    let list = Box::from_raw(ptr::slice_from_raw_parts_mut(displays, count));
+    for display in list.iter() {
+        drop(CString::from_raw(display.id));
+        drop(CString::from_raw(display.name));
+    }
+}
+
+/// Gets the raw input select value of the current input of the monitor with
+/// device ID `id`, e.g., 0x11 for HDMI 1.
+///
+/// # Safety
+/// `id` must be null or a nul-terminated string, and `input` must be null or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn chmi_get_input(
+    id: *const c_char,
+    input: *mut u8,
+) -> i32 {
+    if input.is_null() {
+        return CHMI_ERROR_INVALID_ARGUMENT;
+    }
+
+    with_monitor(id, |monitor| {
+        *input = monitor.input()?.into();
+        Ok(())
+    })
+}
+
+/// Switches the monitor with device ID `id` to the input with the raw input
+/// select value `input`, and waits for the monitor to report it.
+///
+/// # Safety
+/// `id` must be null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn chmi_set_input(id: *const c_char, input: u8) -> i32 {
+    with_monitor(id, |monitor| monitor.set_input(Input::from(input)))
+}
+
+/// Calls `f` with the monitor with device ID `id`, and returns the error code
+/// for the outcome.
+///
+/// # Safety
+/// `id` must be null or a nul-terminated string.
+unsafe fn with_monitor(
+    id: *const c_char,
+    f: impl FnOnce(&mut dyn Monitor) -> anyhow::Result<()>,
+) -> i32 {
+    if id.is_null() {
+        return CHMI_ERROR_INVALID_ARGUMENT;
+    }
+    let Ok(id) = CStr::from_ptr(id).to_str() else {
+        return CHMI_ERROR_INVALID_ARGUMENT;
+    };
+
+    let mut monitors = match crate::get_monitors(&Options::default()) {
+        Ok(monitors) => monitors,
+        Err(err) => {
+            error!("{:#}", err);
+            return CHMI_ERROR_FAILED;
+        }
+    };
+    let Some(monitor) = monitors.iter_mut().find(|monitor| monitor.id() == id)
+    else {
+        return CHMI_ERROR_NOT_FOUND;
+    };
+
+    match f(monitor.as_mut()) {
+        Ok(()) => CHMI_OK,
+        Err(err) => {
+            error!("{:#}", err);
+            CHMI_ERROR_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_arguments() {
+        unsafe {
+            assert_eq!(
+                chmi_list_displays(ptr::null_mut(), ptr::null_mut()),
+                CHMI_ERROR_INVALID_ARGUMENT
+            );
+            let mut input = 0;
+            assert_eq!(
+                chmi_get_input(ptr::null(), &mut input),
+                CHMI_ERROR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                chmi_get_input(c"id".as_ptr(), ptr::null_mut()),
+                CHMI_ERROR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                chmi_set_input(ptr::null(), 0x11),
+                CHMI_ERROR_INVALID_ARGUMENT
+            );
+            chmi_free_displays(ptr::null_mut(), 0);
+        }
+    }
+
+    /// Lists the monitors, reads the input of each, and frees the list, like
+    /// a C caller would. Machines without monitors only list none. Talks to
+    /// real monitors, so run it with `cargo test --features ffi -- --ignored`.
+    #[test]
+    #[ignore = "talks to real monitors over DDC/CI"]
+    fn list_displays_and_get_inputs() {
+        unsafe {
+            let mut displays = ptr::null_mut();
+            let mut count = 0;
+            assert_eq!(chmi_list_displays(&mut displays, &mut count), CHMI_OK);
+
+            for i in 0..count {
+                let display = &*displays.add(i);
+                assert!(!display.id.is_null() && !display.name.is_null());
+                let mut input = 0;
+                let result = chmi_get_input(display.id, &mut input);
+                assert!(
+                    result == CHMI_OK || result == CHMI_ERROR_FAILED,
+                    "unexpected error code {}",
+                    result
+                );
+            }
+
+            assert_eq!(
+                chmi_get_input(c"not a device ID".as_ptr(), &mut 0),
+                CHMI_ERROR_NOT_FOUND
+            );
+            chmi_free_displays(displays, count);
+        }
+    }
+}
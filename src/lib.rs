@@ -1,13 +1,81 @@
 mod cache;
 mod cap;
+mod config;
+mod diagnosis;
+mod edid;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod monitor;
+mod options;
 mod parse;
+mod profile;
+mod state;
 mod windows;
 
-use monitor::Monitor;
+use std::path::Path;
 
-pub fn get_monitors() -> anyhow::Result<Vec<Box<dyn Monitor>>> {
-    let monitors = windows::get_monitors()?;
+use anyhow::anyhow;
+#[cfg(feature = "async")]
+use anyhow::Context;
+use cache::CapabilitiesCache;
+pub use cap::{
+    input_name, input_value, is_signed_vcp_code, Capabilities, Input,
+    PowerMode, VcpCode, VcpType, BRIGHTNESS_CODE, CONTRAST_CODE, INPUTS,
+    INPUT_SELECT_CODE, POWER_MODE_CODE,
+};
+pub use config::{Alias, Config, Override};
+pub use diagnosis::{
+    CapabilitiesSource, Check, DisplayDiagnosis, MonitorDiagnosis,
+};
+use directories::ProjectDirs;
+pub use edid::Edid;
+pub use monitor::{adjust_percent, Monitor, SetOutcome};
+pub use options::{CacheMode, Options};
+pub use profile::{Profile, ProfileMonitor};
+pub use state::LastChoice;
+
+/// Returns the directories chmi stores its cache, config, and state in.
+fn project_dirs() -> anyhow::Result<ProjectDirs> {
+    ProjectDirs::from("", "", "chmi")
+        .ok_or(anyhow!("failed to compute the chmi directory locations"))
+}
+
+/// Returns the monitors attached to the system that respond to DDC/CI.
+///
+/// Monitors are ordered by their position on the desktop, left to right and
+/// then top to bottom, so their indices stay the same as long as the display
+/// layout does. Monitors that fail to respond are logged and skipped, unless
+/// [`Options::strict`] is set.
+///
+/// # Errors
+/// Returns `Err` if the monitors can't be enumerated or, with
+/// [`Options::strict`], if any monitor fails to respond.
+pub fn get_monitors(
+    options: &Options,
+) -> anyhow::Result<Vec<Box<dyn Monitor>>> {
+    let monitors = windows::get_monitors(options)?;
+
+    let mut boxed_monitors: Vec<Box<dyn Monitor>> = Vec::new();
+    for monitor in monitors {
+        boxed_monitors.push(Box::new(monitor));
+    }
+
+    Ok(boxed_monitors)
+}
+
+/// Returns the monitors attached to the system that respond to DDC/CI
+/// without blocking the async runtime. See [`get_monitors`].
+///
+/// The DDC/CI requests made while getting monitors can take seconds, so
+/// they're run on tokio's blocking thread pool.
+#[cfg(feature = "async")]
+pub async fn get_monitors_async(
+    options: Options,
+) -> anyhow::Result<Vec<Box<dyn Monitor>>> {
+    let monitors =
+        tokio::task::spawn_blocking(move || windows::get_monitors(&options))
+            .await
+            .context("failed to get monitors on a blocking thread")??;
 
     let mut boxed_monitors: Vec<Box<dyn Monitor>> = Vec::new();
     for monitor in monitors {
@@ -16,3 +84,108 @@ pub fn get_monitors() -> anyhow::Result<Vec<Box<dyn Monitor>>> {
 
     Ok(boxed_monitors)
 }
+
+/// Returns the monitor that the console window is on.
+pub fn get_current_monitor(
+    options: &Options,
+) -> anyhow::Result<Box<dyn Monitor>> {
+    Ok(Box::new(windows::get_current_monitor(options)?))
+}
+
+/// Diagnoses every display and the physical monitors behind it, e.g., to
+/// find out why a monitor is missing from [`get_monitors`]. Failures are
+/// recorded in the diagnoses instead of stopping at the first one.
+pub fn diagnose(options: &Options) -> anyhow::Result<Vec<DisplayDiagnosis>> {
+    windows::diagnose(options)
+}
+
+/// Shows `message` in a toast notification, e.g., to report the outcome of a
+/// switch when chmi runs without a console.
+#[cfg(feature = "notify")]
+pub fn show_notification(message: &str) -> anyhow::Result<()> {
+    windows::show_notification(message)
+}
+
+/// Returns whether the console window is on `monitor`, so switching its
+/// input can hide chmi. Returns false if it can't be told, e.g., when chmi
+/// isn't running in a terminal.
+pub fn is_current_monitor(monitor: &dyn Monitor) -> bool {
+    windows::is_console_on(monitor.id()).unwrap_or(false)
+}
+
+/// Returns the number of displays on the desktop, including ones that don't
+/// respond to DDC/CI.
+pub fn display_count() -> usize {
+    windows::display_count()
+}
+
+/// Returns whether there's only one display on the desktop, the one the
+/// console window is on, so switching its input away from this computer
+/// leaves nothing to see.
+pub fn is_only_display() -> bool {
+    display_count() == 1
+}
+
+/// Calls `on_change` whenever the displays change, e.g., when a monitor is
+/// connected or disconnected. Only returns if watching for changes fails.
+pub fn watch_display_changes(on_change: impl FnMut()) -> anyhow::Result<()> {
+    windows::watch_display_changes(on_change)
+}
+
+/// Parses a monitor's capabilities string, e.g., to reproduce a parse
+/// failure without the monitor.
+pub fn parse_capabilities(
+    capabilities_string: &str,
+) -> anyhow::Result<Capabilities> {
+    parse::parse(capabilities_string)
+}
+
+/// Returns the inputs listed in a monitor's capabilities string, or no
+/// inputs if it doesn't support input select.
+pub fn inputs_from_capabilities(
+    capabilities_string: &str,
+) -> anyhow::Result<Vec<Input>> {
+    let capabilities = parse::parse(capabilities_string)?;
+    Ok(capabilities.inputs().unwrap_or_default())
+}
+
+/// Returns whether `err` was caused by a failed OS call, e.g., a DDC/CI
+/// request the monitor rejected.
+pub fn is_os_error(err: &anyhow::Error) -> bool {
+    windows::is_os_error(err)
+}
+
+/// Removes all cached capabilities strings from the cache in `cache_dir`, or
+/// the default cache. See [`Options::cache_dir`].
+pub fn clear_cache(cache_dir: Option<&Path>) -> anyhow::Result<()> {
+    CapabilitiesCache::new(cache_dir)?.clear()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn un880_inputs_from_capabilities() {
+        let capabilities_string = "(prot(monitor)type(lcd)UN880cmds(01 02 03 0C E3 F3)vcp(02 04 05 08 10 12 14(05 08 0B ) 16 18 1A 52 60( 11 12 0F 00) AC AE B2 B6 C0 C6 C8 C9 D6(01 04) DF 62 8D F4 F5(00 01 02) F6(00 01 02) 4D 4E 4F 15(01 06 11 13 14 15 18 19 28 29 48) F7(00 01 02 03) F8(00 01) F9 E4 E5 E6 E7 E8 E9 EA EB EF FD(00 01) FE(00 01 02) FF)mccs_ver(2.1)mswhql(1))";
+        assert_eq!(
+            inputs_from_capabilities(capabilities_string).unwrap(),
+            vec![
+                Input::Hdmi1,
+                Input::Hdmi2,
+                Input::DisplayPort1,
+                Input::Other(0x00)
+            ]
+        );
+    }
+
+    #[test]
+    fn inputs_from_capabilities_without_input_select() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)model(LCD24)cmds(01 02 03)vcp(10 12))";
+        assert_eq!(
+            inputs_from_capabilities(capabilities_string).unwrap(),
+            vec![]
+        );
+    }
+}
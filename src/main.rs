@@ -1,39 +1,1582 @@
 use std::{
-    io::{self, Write},
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
     process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
 };
 
+use anyhow::{anyhow, bail, Context};
 use argh::FromArgs;
+use chmi::{
+    Alias, CacheMode, Capabilities, Config, Input, LastChoice, Monitor,
+    Options, PowerMode, Profile, ProfileMonitor, SetOutcome, BRIGHTNESS_CODE,
+    CONTRAST_CODE, POWER_MODE_CODE,
+};
 use owo_colors::{OwoColorize, Stream::Stdout};
-use tracing::{error, info, warn, Level};
+use serde::Serialize;
+use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::{fmt, FmtSubscriber};
 
-// TODO: Add subcommands for "raw" mode
-// TODO: Add an option to just try the window the terminal is on via MonitorFromWindow.
-#[derive(FromArgs)]
-#[argh(description = "chmi - change monitor input")]
-struct Args {
-    #[argh(switch, short = 'v', description = "use verbose output")]
-    verbose: bool,
+/// The exit code for when no monitor supports switching inputs.
+const NO_MONITORS_EXIT_CODE: u8 = 2;
+
+/// Whether to show switch outcomes and errors in notifications too, for runs
+/// without a console. Set by `--notify`.
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+
+/// How long to wait for the switch of the display the terminal is on to be
+/// kept before switching back.
+const CURRENT_DISPLAY_REVERT_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(FromArgs)]
+#[argh(
+    description = "chmi - change monitor input",
+    error_code(1, "an error occurred"),
+    error_code(2, "no monitor supports switching inputs")
+)]
+struct Args {
+    #[argh(switch, short = 'v', description = "use verbose output")]
+    verbose: bool,
+
+    #[argh(switch, short = 'q', description = "only print errors")]
+    quiet: bool,
+
+    #[argh(switch, description = "print version information")]
+    version: bool,
+
+    #[argh(switch, description = "print output as JSON")]
+    json: bool,
+
+    #[argh(switch, description = "don't color output")]
+    no_color: bool,
+
+    #[argh(
+        switch,
+        description = "print what would change instead of changing it"
+    )]
+    dry_run: bool,
+
+    #[argh(
+        switch,
+        description = "don't read or write cached monitor capabilities"
+    )]
+    no_cache: bool,
+
+    #[argh(
+        switch,
+        description = "refetch monitor capabilities and update the cache"
+    )]
+    refresh: bool,
+
+    #[argh(
+        option,
+        description = "directory to keep the capabilities cache in, instead of $CHMI_CACHE_DIR or the default one"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "milliseconds to wait for a monitor to respond before skipping it"
+    )]
+    timeout_ms: Option<u64>,
+
+    #[argh(
+        option,
+        description = "milliseconds to wait for a monitor's capabilities, for slow monitors like TVs"
+    )]
+    capabilities_timeout_ms: Option<u64>,
+
+    #[argh(
+        option,
+        description = "how many times to retry a failed DDC/CI request"
+    )]
+    retries: Option<u32>,
+
+    #[argh(
+        option,
+        description = "monitor name, ID, serial:<serial number>, or 1-based index to skip the monitor prompt"
+    )]
+    monitor: Option<String>,
+
+    #[argh(
+        switch,
+        description = "use the monitor the terminal is on to skip the monitor prompt"
+    )]
+    here: bool,
+
+    #[argh(
+        option,
+        description = "input name, e.g., \"HDMI 1\" or \"dp1\", to skip the input prompt"
+    )]
+    input: Option<String>,
+
+    #[argh(
+        option,
+        description = "switch back to the previous input unless the switch is confirmed within this many seconds"
+    )]
+    auto_revert: Option<u64>,
+
+    #[argh(
+        switch,
+        description = "switch the display the terminal is on, or the only display, without confirming or switching back"
+    )]
+    force: bool,
+
+    #[argh(
+        switch,
+        description = "show monitors whose inputs can't be switched in the monitor prompt too"
+    )]
+    all: bool,
+
+    #[argh(
+        switch,
+        description = "show the outcome of the switch in a notification too"
+    )]
+    notify: bool,
+
+    #[argh(
+        switch,
+        description = "fail if any monitor fails to respond instead of skipping it"
+    )]
+    strict: bool,
+
+    #[argh(
+        option,
+        description = "print the parsed capabilities of a monitor, given its name, ID, or 1-based index"
+    )]
+    show_capabilities: Option<String>,
+
+    #[argh(positional, description = "alias from the config file")]
+    alias: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    List(ListArgs),
+    Get(GetArgs),
+    Info(InfoArgs),
+    Cycle(CycleArgs),
+    SetMany(SetManyArgs),
+    Raw(RawArgs),
+    Brightness(BrightnessArgs),
+    Contrast(ContrastArgs),
+    Power(PowerArgs),
+    Profile(ProfileArgs),
+    Watch(WatchArgs),
+    Doctor(DoctorArgs),
+    Cache(CacheArgs),
+    ParseCapabilities(ParseCapabilitiesArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "list", description = "list monitors")]
+struct ListArgs {
+    #[argh(
+        switch,
+        description = "show whether each monitor supports input select and its inputs"
+    )]
+    detailed: bool,
+
+    #[argh(switch, description = "show the current input of each monitor")]
+    active: bool,
+
+    #[argh(
+        option,
+        from_str_fn(parse_list_format),
+        description = "output format: plain (the default), table, or json"
+    )]
+    format: Option<ListFormat>,
+}
+
+/// How `list` prints monitors.
+#[derive(Clone, Copy, PartialEq)]
+enum ListFormat {
+    /// One monitor name per line.
+    Plain,
+    /// Aligned columns with each monitor's index, name, ID, and inputs.
+    Table,
+    Json,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "get",
+    description = "get the current input of a monitor"
+)]
+struct GetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(
+        switch,
+        description = "print the input select value as the monitor reports it instead of the input name"
+    )]
+    raw: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "info",
+    description = "show the details of a monitor from its EDID and capabilities"
+)]
+struct InfoArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "cycle",
+    description = "switch a monitor to its next input"
+)]
+struct CycleArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "set-many",
+    description = "switch several monitors to inputs at once"
+)]
+struct SetManyArgs {
+    #[argh(
+        positional,
+        description = "display=input pairs, separated by spaces or commas"
+    )]
+    pairs: Vec<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "raw", description = "get or set raw VCP codes")]
+struct RawArgs {
+    #[argh(subcommand)]
+    command: RawCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum RawCommand {
+    Get(RawGetArgs),
+    Set(RawSetArgs),
+    Probe(RawProbeArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "get",
+    description = "get the current and maximum value of a VCP code"
+)]
+struct RawGetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(
+        positional,
+        from_str_fn(parse_vcp_code),
+        description = "VCP code in hexadecimal"
+    )]
+    code: u8,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "probe",
+    description = "get the type, current value, and maximum value of a VCP code"
+)]
+struct RawProbeArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(
+        positional,
+        from_str_fn(parse_vcp_code),
+        description = "VCP code in hexadecimal"
+    )]
+    code: u8,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "set", description = "set the value of a VCP code")]
+struct RawSetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(
+        positional,
+        from_str_fn(parse_vcp_code),
+        description = "VCP code in hexadecimal"
+    )]
+    code: u8,
+
+    #[argh(
+        positional,
+        from_str_fn(parse_vcp_value),
+        description = "VCP value in hexadecimal"
+    )]
+    value: u16,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "brightness",
+    description = "get or set the brightness of a monitor"
+)]
+struct BrightnessArgs {
+    #[argh(subcommand)]
+    command: BrightnessCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum BrightnessCommand {
+    Get(BrightnessGetArgs),
+    Set(BrightnessSetArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "get",
+    description = "get the brightness of a monitor as a percentage"
+)]
+struct BrightnessGetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "set",
+    description = "set the brightness of a monitor as a percentage"
+)]
+struct BrightnessSetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(
+        positional,
+        from_str_fn(parse_brightness_change),
+        description = "brightness from 0 to 100, or a change like +10 or -10 (pass -- before a negative change)"
+    )]
+    brightness: BrightnessChange,
+}
+
+/// A brightness to set, either as a percentage or as a change to the current
+/// percentage.
+#[derive(Clone, Copy)]
+enum BrightnessChange {
+    Absolute(u8),
+    Relative(i16),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "contrast",
+    description = "get or set the contrast of a monitor"
+)]
+struct ContrastArgs {
+    #[argh(subcommand)]
+    command: ContrastCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ContrastCommand {
+    Get(ContrastGetArgs),
+    Set(ContrastSetArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "get",
+    description = "get the contrast of a monitor as a percentage"
+)]
+struct ContrastGetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "set",
+    description = "set the contrast of a monitor as a percentage"
+)]
+struct ContrastSetArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(positional, description = "contrast from 0 to 100")]
+    contrast: u8,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "doctor",
+    description = "diagnose each monitor, e.g., for a bug report"
+)]
+struct DoctorArgs {}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "power",
+    description = "set the power mode of a monitor"
+)]
+struct PowerArgs {
+    #[argh(positional, description = "monitor name, ID, or 1-based index")]
+    display: String,
+
+    #[argh(positional, description = "power mode: on, standby, or off")]
+    mode: PowerMode,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "profile",
+    description = "save or apply the inputs of all monitors"
+)]
+struct ProfileArgs {
+    #[argh(subcommand)]
+    command: ProfileCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ProfileCommand {
+    Save(ProfileSaveArgs),
+    Apply(ProfileApplyArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "save",
+    description = "save the current input of each monitor as a profile"
+)]
+struct ProfileSaveArgs {
+    #[argh(positional, description = "profile name")]
+    name: String,
+
+    #[argh(switch, description = "save the brightness of each monitor too")]
+    brightness: bool,
+
+    #[argh(switch, description = "save the contrast of each monitor too")]
+    contrast: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "apply",
+    description = "switch each monitor in a profile to its saved input"
+)]
+struct ProfileApplyArgs {
+    #[argh(positional, description = "profile name")]
+    name: String,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "watch",
+    description = "switch monitors to the inputs of aliases whenever the displays change"
+)]
+struct WatchArgs {
+    #[argh(positional, description = "aliases from the config file")]
+    aliases: Vec<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "cache",
+    description = "manage the monitor capabilities cache"
+)]
+struct CacheArgs {
+    #[argh(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CacheCommand {
+    Clear(CacheClearArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "clear",
+    description = "remove all cached monitor capabilities"
+)]
+struct CacheClearArgs {}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "parse-capabilities",
+    description = "parse a capabilities string and print the result"
+)]
+struct ParseCapabilitiesArgs {
+    #[argh(
+        option,
+        description = "file to read the capabilities string from, or - for stdin (the default)"
+    )]
+    file: Option<String>,
+}
+
+fn parse_list_format(value: &str) -> Result<ListFormat, String> {
+    match value {
+        "plain" => Ok(ListFormat::Plain),
+        "table" => Ok(ListFormat::Table),
+        "json" => Ok(ListFormat::Json),
+        _ => Err(format!(
+            "unknown format '{}', expected plain, table, or json",
+            value
+        )),
+    }
+}
+
+fn parse_brightness_change(value: &str) -> Result<BrightnessChange, String> {
+    if value.starts_with(['+', '-']) {
+        value
+            .parse()
+            .map(BrightnessChange::Relative)
+            .map_err(|err| format!("invalid brightness change: {}", err))
+    } else {
+        value
+            .parse()
+            .map(BrightnessChange::Absolute)
+            .map_err(|err| format!("invalid brightness: {}", err))
+    }
+}
+
+fn parse_vcp_code(value: &str) -> Result<u8, String> {
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u8::from_str_radix(value, 16).map_err(|err| err.to_string())
+}
+
+fn parse_vcp_value(value: &str) -> Result<u16, String> {
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u16::from_str_radix(value, 16).map_err(|err| err.to_string())
+}
+
+/// Returns the input after `current` in `inputs`, wrapping around to the
+/// first input. Starts from the first input if `current` isn't in `inputs`.
+fn next_input(inputs: &[Input], current: Option<Input>) -> Option<Input> {
+    let next = match current.and_then(|c| inputs.iter().position(|&i| i == c))
+    {
+        Some(i) => (i + 1) % inputs.len(),
+        None => 0,
+    };
+    inputs.get(next).copied()
+}
+
+/// An error for a monitor that doesn't match any connected monitor.
+#[derive(Debug)]
+struct MonitorNotFound {
+    display: String,
+}
+
+impl std::fmt::Display for MonitorNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unable to find monitor '{}'", self.display)
+    }
+}
+
+impl std::error::Error for MonitorNotFound {}
+
+/// Returns the index of the monitor named `display` or with the ID
+/// `display`, or, if no monitor matches, the index for the 1-based index
+/// `display`. A `display` of `serial:<serial number>` only matches the
+/// monitor with that EDID serial number.
+///
+/// # Errors
+/// Returns `Err` if no monitor matches or multiple monitors have the name.
+fn find_monitor_index(
+    monitors: &[Box<dyn Monitor>],
+    display: &str,
+) -> anyhow::Result<usize> {
+    if let Some(serial) = display.strip_prefix("serial:") {
+        return monitors
+            .iter()
+            .position(|monitor| monitor.serial() == Some(serial))
+            .ok_or_else(|| {
+                MonitorNotFound { display: display.to_owned() }.into()
+            });
+    }
+
+    let mut indices = monitors
+        .iter()
+        .enumerate()
+        .filter(|(_, monitor)| {
+            monitor.name() == display || monitor.id() == display
+        })
+        .map(|(i, _)| i);
+
+    match (indices.next(), indices.next()) {
+        (Some(index), None) => Ok(index),
+        (Some(_), Some(_)) => bail!(
+            "multiple monitors are named '{}', use an index instead",
+            display
+        ),
+        (None, _) => display
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .filter(|&i| i < monitors.len())
+            .ok_or_else(|| {
+                MonitorNotFound { display: display.to_owned() }.into()
+            }),
+    }
+}
+
+/// Returns the monitor for `display`. See [`find_monitor_index`].
+fn find_monitor<'a>(
+    monitors: &'a mut [Box<dyn Monitor>],
+    display: &str,
+) -> anyhow::Result<&'a mut dyn Monitor> {
+    let index = find_monitor_index(monitors, display)?;
+    Ok(monitors[index].as_mut())
+}
+
+#[derive(Serialize)]
+struct ListOutput<'a> {
+    id: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serial: Option<&'a str>,
+    supports_input_select: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inputs: Option<Vec<Input>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_input: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    error: ErrorDetails<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails<'a> {
+    /// A stable name for the kind of error, e.g., "DisplayNotFound".
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display: Option<&'a str>,
+    message: String,
+}
+
+/// Logs `err`, or, with `json`, prints it to stderr as JSON so that scripts
+/// can tell kinds of errors apart.
+fn report_error(err: &anyhow::Error, json: bool) {
+    notify(&format!("{:#}", err));
+
+    if !json {
+        error!("{:#}", err);
+        return;
+    }
+
+    let not_found = err.downcast_ref::<MonitorNotFound>();
+    let kind = if not_found.is_some() {
+        "DisplayNotFound"
+    } else if chmi::is_os_error(err) {
+        "Os"
+    } else {
+        "Other"
+    };
+    let output = ErrorOutput {
+        error: ErrorDetails {
+            kind,
+            display: not_found.map(|not_found| not_found.display.as_str()),
+            message: format!("{:#}", err),
+        },
+    };
+    match serde_json::to_string(&output) {
+        Ok(output) => eprintln!("{}", output),
+        Err(_) => error!("{:#}", err),
+    }
+}
+
+/// Shows `message` in a notification if `--notify` was passed.
+#[cfg(feature = "notify")]
+fn notify(message: &str) {
+    if !NOTIFY.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Err(err) = chmi::show_notification(message) {
+        warn!("{:#}", err);
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+fn notify(_message: &str) {}
+
+#[derive(Serialize)]
+struct GetOutput<'a> {
+    id: &'a str,
+    input: Input,
+    raw: u16,
+}
+
+#[derive(Serialize)]
+struct InfoOutput<'a> {
+    id: &'a str,
+    name: &'a str,
+    manufacturer: Option<&'a str>,
+    model: Option<&'a str>,
+    serial: Option<&'a str>,
+    native_resolution: Option<(u16, u16)>,
+    mccs_version: Option<&'a str>,
+}
+
+/// Prints `rows` under `headers` in left-aligned columns that fit the
+/// widest cell in each column.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> =
+        headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(headers.to_vec());
+    for row in rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
+
+/// Returns the name of the current input of `monitor`, "-" if it doesn't
+/// support input select, or "?" if reading its input fails.
+fn active_input_name(monitor: &dyn Monitor) -> String {
+    if !monitor.capabilities().has_input_select() {
+        return "-".to_owned();
+    }
+
+    match monitor.input() {
+        Ok(input) => input.to_string(),
+        Err(err) => {
+            warn!("{:#}", err);
+            "?".to_owned()
+        }
+    }
+}
+
+fn list(
+    monitors: &[Box<dyn Monitor>],
+    args: ListArgs,
+    json: bool,
+) -> anyhow::Result<()> {
+    let format = if json {
+        ListFormat::Json
+    } else {
+        args.format.unwrap_or(ListFormat::Plain)
+    };
+
+    // Read every active input up front since reading them is slow.
+    let active_inputs: Vec<Option<String>> = monitors
+        .iter()
+        .map(|monitor| {
+            args.active.then(|| active_input_name(monitor.as_ref()))
+        })
+        .collect();
+
+    match format {
+        ListFormat::Json => {
+            let output: Vec<ListOutput> = monitors
+                .iter()
+                .zip(&active_inputs)
+                .map(|(monitor, active_input)| ListOutput {
+                    id: monitor.id(),
+                    name: monitor.name(),
+                    serial: monitor.serial(),
+                    supports_input_select: monitor
+                        .capabilities()
+                        .has_input_select(),
+                    inputs: args.detailed.then(|| {
+                        monitor.capabilities().inputs().unwrap_or_default()
+                    }),
+                    active_input: active_input.as_deref(),
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&output)?);
+        }
+        ListFormat::Table => {
+            let mut headers = vec!["#", "NAME", "ID", "INPUTS"];
+            if args.active {
+                headers.push("ACTIVE");
+            }
+
+            let rows: Vec<Vec<String>> = monitors
+                .iter()
+                .zip(&active_inputs)
+                .enumerate()
+                .map(|(i, (monitor, active_input))| {
+                    let capabilities = monitor.capabilities();
+                    let inputs = match capabilities.inputs() {
+                        Some(inputs) => inputs
+                            .iter()
+                            .map(|&input| capabilities.input_name(input))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        None => "-".to_owned(),
+                    };
+                    let mut row = vec![
+                        (i + 1).to_string(),
+                        monitor.name().to_owned(),
+                        monitor.id().to_owned(),
+                        inputs,
+                    ];
+                    row.extend(active_input.clone());
+                    row
+                })
+                .collect();
+            print_table(&headers, &rows);
+        }
+        ListFormat::Plain => {
+            for (i, (monitor, active_input)) in
+                monitors.iter().zip(&active_inputs).enumerate()
+            {
+                let active_input = active_input
+                    .as_ref()
+                    .map(|active_input| format!(" ({})", active_input))
+                    .unwrap_or_default();
+                println!(
+                    "  {} {}{}",
+                    (i + 1)
+                        .if_supports_color(Stdout, |text| text.bright_cyan()),
+                    monitor.name(),
+                    active_input
+                );
+
+                if args.detailed {
+                    if let Some(serial) = monitor.serial() {
+                        println!("    serial: {}", serial);
+                    }
+                    let capabilities = monitor.capabilities();
+                    match capabilities.inputs() {
+                        Some(inputs) if inputs.is_empty() => {
+                            println!("    doesn't list its inputs")
+                        }
+                        Some(inputs) => {
+                            let names: Vec<String> = inputs
+                                .iter()
+                                .map(|&input| capabilities.input_name(input))
+                                .collect();
+                            println!("    inputs: {}", names.join(", "));
+                        }
+                        None => println!("    doesn't support input select"),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get(
+    monitors: &mut [Box<dyn Monitor>],
+    args: GetArgs,
+    json: bool,
+) -> anyhow::Result<()> {
+    let monitor = find_monitor(monitors, &args.display)?;
+    // Read the value once for both the input and the unmodified value.
+    let (raw, _) = monitor.vcp(monitor.input_select_code())?;
+    let input = Input::from(chmi::input_value(raw));
+
+    if json {
+        let output = GetOutput { id: monitor.id(), input, raw };
+        println!("{}", serde_json::to_string(&output)?);
+    } else if args.raw {
+        println!("{}", raw);
+    } else {
+        println!("{}", input);
+    }
+
+    Ok(())
+}
+
+fn info(
+    monitors: &mut [Box<dyn Monitor>],
+    args: InfoArgs,
+    json: bool,
+) -> anyhow::Result<()> {
+    let monitor = find_monitor(monitors, &args.display)?;
+    let edid = monitor.edid();
+    // Not every EDID has a model name, but most capabilities strings do.
+    let output = InfoOutput {
+        id: monitor.id(),
+        name: monitor.name(),
+        manufacturer: edid.map(|edid| edid.manufacturer.as_str()),
+        model: edid
+            .and_then(|edid| edid.model.as_deref())
+            .or(monitor.capabilities().model.as_deref()),
+        serial: monitor.serial(),
+        native_resolution: edid.and_then(|edid| edid.native_resolution),
+        mccs_version: monitor.capabilities().mccs_version.as_deref(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
+
+    const UNKNOWN: &str = "unknown";
+    println!("name: {}", output.name);
+    println!("id: {}", output.id);
+    println!("manufacturer: {}", output.manufacturer.unwrap_or(UNKNOWN));
+    println!("model: {}", output.model.unwrap_or(UNKNOWN));
+    println!("serial: {}", output.serial.unwrap_or(UNKNOWN));
+    match output.native_resolution {
+        Some((width, height)) => {
+            println!("native resolution: {}x{}", width, height)
+        }
+        None => println!("native resolution: {}", UNKNOWN),
+    }
+    println!("MCCS version: {}", output.mccs_version.unwrap_or(UNKNOWN));
+
+    Ok(())
+}
+
+fn cycle(
+    monitors: &mut [Box<dyn Monitor>],
+    args: CycleArgs,
+    dry_run: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let monitor = find_monitor(monitors, &args.display)?;
+
+    let current = monitor.input()?;
+
+    let inputs = monitor.capabilities().inputs().unwrap_or_default();
+    let next = next_input(&inputs, Some(current)).ok_or_else(|| {
+        anyhow!("monitor '{}' doesn't have any inputs", monitor.name())
+    })?;
+
+    if dry_run {
+        println!("would set {} from {} to {}", monitor.name(), current, next);
+    } else if next != current
+        && confirm_only_display_switch(
+            monitor,
+            next,
+            force,
+            io::stdin().is_terminal(),
+        )?
+    {
+        monitor.set_input(next)?;
+    }
+
+    Ok(())
+}
+
+/// Switches the monitor of each `display=input` pair to the input. Failures
+/// are logged, and the remaining pairs are still applied.
+fn set_many(
+    monitors: &mut [Box<dyn Monitor>],
+    args: SetManyArgs,
+    dry_run: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let pairs = args
+        .pairs
+        .iter()
+        .flat_map(|pairs| pairs.split(','))
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(display, input)| (display.trim(), input.trim()))
+                .ok_or_else(|| {
+                    anyhow!("expected display=input, found '{}'", pair)
+                })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if pairs.is_empty() {
+        bail!("expected at least one display=input pair");
+    }
+
+    let mut failures = 0;
+    for &(display_name, input) in &pairs {
+        let result = (|| {
+            let monitor = find_monitor(monitors, display_name)?;
+            let input = monitor.capabilities().find_input(input)?;
+            if dry_run {
+                println!("would set {} to {}", monitor.name(), input);
+            } else if confirm_only_display_switch(
+                monitor,
+                input,
+                force,
+                io::stdin().is_terminal(),
+            )? {
+                match monitor.set_input_checked(input)? {
+                    SetOutcome::Unchanged => {
+                        info!("{} is already on {}", monitor.name(), input)
+                    }
+                    SetOutcome::Changed { from, to } => info!(
+                        "switched {} from {} to {}",
+                        monitor.name(),
+                        from,
+                        to
+                    ),
+                }
+            }
+            anyhow::Ok(())
+        })();
+        if let Err(err) = result {
+            error!("failed to set '{}': {:#}", display_name, err);
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        bail!("failed to set {} of {} monitors", failures, pairs.len());
+    }
+
+    Ok(())
+}
+
+fn profile(
+    monitors: &mut [Box<dyn Monitor>],
+    args: ProfileArgs,
+    dry_run: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    match args.command {
+        ProfileCommand::Save(args) => {
+            let mut profile = Profile::default();
+            let mut failures = 0;
+            for monitor in monitors.iter() {
+                if !monitor.capabilities().has_input_select() {
+                    debug!(
+                        "leaving monitor '{}' out of the profile since it doesn't support input select",
+                        monitor.name()
+                    );
+                    continue;
+                }
+
+                let capabilities = monitor.capabilities();
+                let result = (|| {
+                    let brightness = if args.brightness
+                        && capabilities.has_vcp_code(BRIGHTNESS_CODE)
+                    {
+                        Some(monitor.brightness()?)
+                    } else {
+                        None
+                    };
+                    let contrast = if args.contrast
+                        && capabilities.has_vcp_code(CONTRAST_CODE)
+                    {
+                        Some(monitor.contrast()?)
+                    } else {
+                        None
+                    };
+                    anyhow::Ok(ProfileMonitor {
+                        id: monitor.id().to_owned(),
+                        serial: monitor.serial().map(str::to_owned),
+                        name: monitor.name().to_owned(),
+                        input: monitor.input()?.into(),
+                        brightness,
+                        contrast,
+                    })
+                })();
+                match result {
+                    Ok(saved) => profile.monitors.push(saved),
+                    Err(err) => {
+                        error!(
+                            "leaving monitor '{}' out of the profile: {:#}",
+                            monitor.name(),
+                            err
+                        );
+                        failures += 1;
+                    }
+                }
+            }
+
+            // Don't replace a saved profile with an empty one.
+            if profile.monitors.is_empty() && failures > 0 {
+                bail!("failed to save any of {} monitors", failures);
+            }
+
+            if dry_run {
+                println!(
+                    "would save {} monitor(s) as profile '{}'",
+                    profile.monitors.len(),
+                    args.name
+                );
+            } else {
+                profile.save(&args.name)?;
+                info!(
+                    "saved {} monitor(s) as profile '{}'",
+                    profile.monitors.len(),
+                    args.name
+                );
+            }
+
+            if failures > 0 {
+                bail!(
+                    "failed to save {} of {} monitors",
+                    failures,
+                    failures + profile.monitors.len()
+                );
+            }
+        }
+        ProfileCommand::Apply(args) => {
+            let profile = Profile::load(&args.name)?;
+
+            let mut failures = 0;
+            for saved in &profile.monitors {
+                let Some(monitor) = monitors
+                    .iter_mut()
+                    .find(|monitor| saved.matches(monitor.as_ref()))
+                else {
+                    warn!(
+                        "skipping monitor '{}' since it isn't connected",
+                        saved.name
+                    );
+                    continue;
+                };
+
+                let input = Input::from(saved.input);
+                let result = (|| {
+                    if dry_run {
+                        println!("would set {} to {}", monitor.name(), input);
+                        if let Some(brightness) = saved.brightness {
+                            println!(
+                                "would set the brightness of {} to {}",
+                                monitor.name(),
+                                brightness
+                            );
+                        }
+                        if let Some(contrast) = saved.contrast {
+                            println!(
+                                "would set the contrast of {} to {}",
+                                monitor.name(),
+                                contrast
+                            );
+                        }
+                        return anyhow::Ok(());
+                    }
+
+                    if !confirm_only_display_switch(
+                        monitor.as_ref(),
+                        input,
+                        force,
+                        io::stdin().is_terminal(),
+                    )? {
+                        return anyhow::Ok(());
+                    }
+
+                    // Set the brightness and contrast first since the monitor
+                    // might stop responding once it's on an input without a
+                    // signal.
+                    if let Some(brightness) = saved.brightness {
+                        monitor.set_brightness(brightness)?;
+                    }
+                    if let Some(contrast) = saved.contrast {
+                        monitor.set_contrast(contrast)?;
+                    }
+                    if monitor.input()? == input {
+                        info!("{} is already on {}", monitor.name(), input);
+                    } else {
+                        set_input_value(monitor.as_mut(), saved.input)?;
+                        info!("switched {} to {}", monitor.name(), input);
+                    }
+                    anyhow::Ok(())
+                })();
+                if let Err(err) = result {
+                    error!("failed to apply '{}': {:#}", saved.name, err);
+                    failures += 1;
+                }
+            }
+
+            if failures > 0 {
+                bail!(
+                    "failed to apply {} of {} monitors",
+                    failures,
+                    profile.monitors.len()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn raw(
+    monitors: &mut [Box<dyn Monitor>],
+    args: RawArgs,
+    dry_run: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    match args.command {
+        RawCommand::Get(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            if chmi::is_signed_vcp_code(args.code) {
+                let (current, maximum) = monitor.vcp_signed(args.code)?;
+                println!(
+                    "current: {} ({:#06X}), maximum: {} ({:#06X})",
+                    current, current, maximum, maximum
+                );
+            } else {
+                let (current, maximum) = monitor.vcp(args.code)?;
+                println!(
+                    "current: {} ({:#06X}), maximum: {} ({:#06X})",
+                    current, current, maximum, maximum
+                );
+            }
+        }
+        RawCommand::Set(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            if !monitor.capabilities().supports_set_vcp() {
+                bail!(
+                    "monitor '{}' doesn't support setting VCP codes",
+                    monitor.name()
+                );
+            }
+            if dry_run {
+                let (current, _) = monitor.vcp(args.code)?;
+                println!(
+                    "would set VCP code {:#04X} of {} from {:#06X} to {:#06X}",
+                    args.code,
+                    monitor.name(),
+                    current,
+                    args.value
+                );
+            } else if args.code != monitor.input_select_code()
+                // Input select values fit in the low byte.
+                || confirm_only_display_switch(
+                    monitor,
+                    Input::from(args.value as u8),
+                    force,
+                    io::stdin().is_terminal(),
+                )?
+            {
+                monitor.set_vcp(args.code, args.value)?;
+            }
+        }
+        RawCommand::Probe(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            let (vcp_type, current, maximum) =
+                monitor.vcp_with_type(args.code)?;
+            println!(
+                "type: {}, current: {} ({:#06X}), maximum: {} ({:#06X})",
+                vcp_type, current, current, maximum, maximum
+            );
+        }
+    }
+
+    Ok(())
+}
 
-    #[argh(switch, description = "print version information")]
-    version: bool,
+fn brightness(
+    monitors: &mut [Box<dyn Monitor>],
+    args: BrightnessArgs,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    match args.command {
+        BrightnessCommand::Get(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            println!("{}", monitor.brightness()?);
+        }
+        BrightnessCommand::Set(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            if !monitor.capabilities().supports_set_vcp() {
+                bail!(
+                    "monitor '{}' doesn't support setting VCP codes",
+                    monitor.name()
+                );
+            }
+            if !monitor.capabilities().has_vcp_code(BRIGHTNESS_CODE) {
+                bail!(
+                    "monitor '{}' doesn't support brightness",
+                    monitor.name()
+                );
+            }
+
+            match args.brightness {
+                BrightnessChange::Absolute(percent) if dry_run => println!(
+                    "would set the brightness of {} from {}% to {}%",
+                    monitor.name(),
+                    monitor.brightness()?,
+                    percent.min(100)
+                ),
+                BrightnessChange::Absolute(percent) => {
+                    monitor.set_brightness(percent)?
+                }
+                BrightnessChange::Relative(delta) if dry_run => {
+                    let current = monitor.brightness()?;
+                    println!(
+                        "would set the brightness of {} from {}% to {}%",
+                        monitor.name(),
+                        current,
+                        chmi::adjust_percent(current, delta)
+                    );
+                }
+                // Print the new brightness since it isn't obvious from the
+                // change, e.g., for key bindings that show it.
+                BrightnessChange::Relative(delta) => {
+                    println!("{}", monitor.adjust_brightness(delta)?)
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn contrast(
+    monitors: &mut [Box<dyn Monitor>],
+    args: ContrastArgs,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    match args.command {
+        ContrastCommand::Get(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            println!("{}", monitor.contrast()?);
+        }
+        ContrastCommand::Set(args) => {
+            let monitor = find_monitor(monitors, &args.display)?;
+            if !monitor.capabilities().supports_set_vcp() {
+                bail!(
+                    "monitor '{}' doesn't support setting VCP codes",
+                    monitor.name()
+                );
+            }
+
+            if dry_run {
+                println!(
+                    "would set the contrast of {} from {}% to {}%",
+                    monitor.name(),
+                    monitor.contrast()?,
+                    args.contrast.min(100)
+                );
+            } else {
+                monitor.set_contrast(args.contrast)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn power(
+    monitors: &mut [Box<dyn Monitor>],
+    args: PowerArgs,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let monitor = find_monitor(monitors, &args.display)?;
+    let capabilities = monitor.capabilities();
+    if !capabilities.supports_set_vcp() {
+        bail!(
+            "monitor '{}' doesn't support setting VCP codes",
+            monitor.name()
+        );
+    }
+
+    let value = u8::from(args.mode);
+    match capabilities.values_for(POWER_MODE_CODE) {
+        Some(values) if values.contains(&value) => {}
+        Some(_) => warn!(
+            "monitor '{}' doesn't list power mode {} ({:#04X}), trying anyway",
+            monitor.name(),
+            args.mode,
+            value
+        ),
+        None => {
+            bail!("monitor '{}' doesn't support power modes", monitor.name())
+        }
+    }
+
+    if dry_run {
+        println!(
+            "would set the power mode of {} to {}",
+            monitor.name(),
+            args.mode
+        );
+        return Ok(());
+    }
+
+    monitor.set_vcp(POWER_MODE_CODE, value.into())
+}
+
+/// Switches the monitor of each alias to the alias's input, unless it's
+/// already on it. Monitors that aren't connected are skipped.
+/// Nothing can be confirmed while watching, so switching the only display
+/// takes `force`.
+fn apply_aliases(options: &Options, aliases: &[&Alias], force: bool) {
+    let mut monitors = match chmi::get_monitors(options) {
+        Ok(monitors) => monitors,
+        Err(err) => {
+            error!("{:#}", err);
+            return;
+        }
+    };
+
+    for alias in aliases {
+        let Ok(index) = find_monitor_index(&monitors, &alias.display) else {
+            debug!(
+                "skipping monitor '{}' since it isn't connected",
+                alias.display
+            );
+            continue;
+        };
+        let monitor = monitors[index].as_mut();
+
+        let result = (|| {
+            let input = monitor.capabilities().find_input(&alias.input)?;
+            confirm_only_display_switch(monitor, input, force, false)?;
+            if let SetOutcome::Changed { .. } =
+                monitor.set_input_checked(input)?
+            {
+                info!("switched monitor '{}' to {}", monitor.name(), input);
+            }
+            anyhow::Ok(())
+        })();
+        if let Err(err) = result {
+            error!("{:#}", err);
+        }
+    }
+}
+
+fn watch(
+    options: &Options,
+    config: &Config,
+    args: WatchArgs,
+    force: bool,
+) -> anyhow::Result<()> {
+    if args.aliases.is_empty() {
+        bail!("no aliases to watch");
+    }
+
+    let aliases = args
+        .aliases
+        .iter()
+        .map(|name| {
+            config
+                .aliases
+                .get(name)
+                .ok_or_else(|| anyhow!("unable to find alias '{}'", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    apply_aliases(options, &aliases, force);
+    chmi::watch_display_changes(|| {
+        info!("displays changed");
+        apply_aliases(options, &aliases, force);
+    })
+}
+
+fn cache(args: CacheArgs, cache_dir: Option<&Path>) -> anyhow::Result<()> {
+    match args.command {
+        CacheCommand::Clear(_) => chmi::clear_cache(cache_dir),
+    }
+}
+
+fn doctor(options: &Options) -> anyhow::Result<()> {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    println!("displays: {}", chmi::display_count());
+
+    for (i, diagnosis) in chmi::diagnose(options)?.iter().enumerate() {
+        println!("\ndisplay {}: {}", i + 1, diagnosis);
+    }
+
+    Ok(())
+}
+
+fn parse_capabilities(
+    args: ParseCapabilitiesArgs,
+    json: bool,
+) -> anyhow::Result<()> {
+    let capabilities_string = match args.file.as_deref() {
+        None | Some("-") => io::read_to_string(io::stdin())
+            .context("failed to read the capabilities string from stdin")?,
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("failed to read '{}'", path))?,
+    };
+
+    // Capabilities strings copied from a terminal or saved to a file usually
+    // end with a newline, which the parser rejects.
+    let capabilities = chmi::parse_capabilities(capabilities_string.trim())?;
+    print_capabilities(&capabilities, json)
 }
 
-fn get_choice(prompt: &str, choices: &[usize]) -> usize {
+/// Prints capabilities for people, or, with `json`, as JSON for other tools.
+fn print_capabilities(
+    capabilities: &Capabilities,
+    json: bool,
+) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(capabilities)?);
+    } else {
+        println!("{}", capabilities);
+    }
+
+    Ok(())
+}
+
+/// Prompts for one of `choices`. If there's a default, an empty answer
+/// chooses it.
+fn get_choice(
+    prompt: &str,
+    choices: &[usize],
+    default: Option<usize>,
+) -> usize {
     let choices_string = choices
         .iter()
         .map(|i| i.to_string())
         .collect::<Vec<String>>()
         .join("/");
+    let default_string =
+        default.map(|default| format!(" [{}]", default)).unwrap_or_default();
 
     let choice: usize;
     loop {
         print!(
-            "{} {} ({}): ",
+            "{} {} ({}){}: ",
             "==>".if_supports_color(Stdout, |text| text.bright_yellow()),
             prompt,
-            choices_string
+            choices_string,
+            default_string
         );
         let _ = io::stdout().flush();
 
@@ -42,6 +1585,11 @@ fn get_choice(prompt: &str, choices: &[usize]) -> usize {
             .read_line(&mut input)
             .expect("reading from stdin should succeed");
 
+        if let Some(default) = default.filter(|_| input.trim().is_empty()) {
+            choice = default;
+            break;
+        }
+
         if let Ok(input) = input.trim().parse::<usize>() {
             if choices.contains(&input) {
                 choice = input;
@@ -53,105 +1601,653 @@ fn get_choice(prompt: &str, choices: &[usize]) -> usize {
     choice
 }
 
+/// Returns whether to switch `monitor` to `input`, making sure the switch is
+/// intended if the monitor is the only display. Switching it to an input
+/// without a signal leaves no way to see chmi, let alone switch back, and
+/// chmi can't tell whether an input has a signal. Switching to the current
+/// input is always fine.
+///
+/// With `ask`, the user is asked to confirm. Otherwise, e.g., for scripts,
+/// the switch is refused unless `force` is set.
+///
+/// # Errors
+/// Returns `Err` if the switch is refused or reading the current input
+/// fails.
+fn confirm_only_display_switch(
+    monitor: &dyn Monitor,
+    input: Input,
+    force: bool,
+    ask: bool,
+) -> anyhow::Result<bool> {
+    if force || !chmi::is_only_display() || monitor.input()? == input {
+        return Ok(true);
+    }
+    if !ask {
+        bail!(
+            "{} is the only display, pass --force to switch it to {}",
+            monitor.name(),
+            input
+        );
+    }
+
+    Ok(confirm(&format!(
+        "{} is the only display, switch it to {} anyway?",
+        monitor.name(),
+        input
+    )))
+}
+
+/// Asks a yes or no question, and returns whether it's answered yes.
+fn confirm(prompt: &str) -> bool {
+    print!(
+        "{} {} (y/N): ",
+        "==>".if_supports_color(Stdout, |text| text.bright_yellow()),
+        prompt
+    );
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .expect("reading from stdin should succeed");
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Asks a yes or no question, and returns whether it's answered yes within
+/// `timeout`.
+fn confirm_within(prompt: &str, timeout: Duration) -> bool {
+    print!(
+        "{} {} (y/N, {} s): ",
+        "==>".if_supports_color(Stdout, |text| text.bright_yellow()),
+        prompt,
+        timeout.as_secs()
+    );
+    let _ = io::stdout().flush();
+
+    read_line_within(timeout).is_some_and(|answer| {
+        matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+    })
+}
+
+/// Reads a line from stdin, or returns `None` if there isn't one within
+/// `timeout`.
+fn read_line_within(timeout: Duration) -> Option<String> {
+    // Reading from stdin can't time out, so read on another thread and leave
+    // it blocked if there's no answer in time.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).is_ok() {
+            let _ = sender.send(answer);
+        }
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(answer) => Some(answer),
+        Err(_) => {
+            println!();
+            None
+        }
+    }
+}
+
+/// Switches `monitor` to the input with the raw value `value`, verifying the
+/// switch if chmi knows the input.
+fn set_input_value(
+    monitor: &mut dyn Monitor,
+    value: u8,
+) -> anyhow::Result<()> {
+    match Input::from(value) {
+        Input::Other(_) => monitor.set_input_by_raw(value),
+        input => monitor.set_input(input),
+    }
+}
+
+/// Returns why the inputs of `monitor` can't be switched, if they can't.
+fn unsupported_reason(monitor: &dyn Monitor) -> Option<&'static str> {
+    let capabilities = monitor.capabilities();
+    if !capabilities.has_input_select() {
+        return Some("doesn't support input select");
+    }
+    // Some monitors list the input select code without its values, so
+    // there's nothing to offer.
+    if capabilities.inputs().is_some_and(|inputs| inputs.is_empty()) {
+        return Some("supports input select but doesn't list its inputs");
+    }
+    if !capabilities.supports_set_vcp() {
+        return Some("doesn't support setting VCP codes");
+    }
+    None
+}
+
+/// Prints the monitors whose inputs can't be switched under the monitor
+/// choices, without a number since they can't be chosen.
+fn print_unsupported_monitors(monitors: &[Box<dyn Monitor>]) {
+    for monitor in monitors {
+        let reason = unsupported_reason(monitor.as_ref()).unwrap_or_default();
+        println!(
+            "  {} {} (unsupported, it {})",
+            "-".if_supports_color(Stdout, |text| text.bright_black()),
+            monitor.name(),
+            reason
+        );
+    }
+}
+
+/// Prompts for one of the inputs in `capabilities`, marking the current
+/// input, and returns the raw value of the chosen input. `default_value` is
+/// chosen for an empty answer.
+fn prompt_input(
+    capabilities: &Capabilities,
+    curr_value: u8,
+    default_value: Option<u8>,
+) -> u8 {
+    let inputs = capabilities.inputs().unwrap();
+    let values: Vec<u8> =
+        inputs.iter().map(|&input| u8::from(input)).collect();
+
+    let mut input_choices = Vec::new();
+    for (i, &value) in values.iter().enumerate() {
+        input_choices.push(i + 1);
+
+        let name = capabilities.input_name(Input::from(value));
+        if value == curr_value {
+            println!(
+                "  {} {} (*)",
+                (i + 1).if_supports_color(Stdout, |text| text.bright_cyan()),
+                name
+            );
+        } else {
+            println!(
+                "  {} {}",
+                (i + 1).if_supports_color(Stdout, |text| text.bright_cyan()),
+                name
+            );
+        }
+    }
+
+    // Offer switching to the next input as the last choice.
+    let next = next_input(&inputs, Some(Input::from(curr_value)));
+    if next.is_some() {
+        input_choices.push(values.len() + 1);
+        println!(
+            "  {} Next input",
+            (values.len() + 1)
+                .if_supports_color(Stdout, |text| text.bright_cyan()),
+        );
+    }
+
+    let default = default_value
+        .and_then(|default| values.iter().position(|&value| value == default))
+        .map(|i| i + 1);
+    let input_choice = get_choice("Input", &input_choices, default);
+    match values.get(input_choice - 1) {
+        Some(&value) => value,
+        None => next
+            .expect("the next input should exist if it was a choice")
+            .into(),
+    }
+}
+
 fn main() -> ExitCode {
     let args: Args = argh::from_env();
 
     if args.version {
-        // TODO: Print the commit hash too.
-        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        match option_env!("CHMI_COMMIT_HASH") {
+            Some(hash) => println!(
+                "{} {} ({})",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                hash
+            ),
+            None => println!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+        }
         return ExitCode::SUCCESS;
     }
 
-    if args.verbose {
-        let format = fmt::format().with_target(false).without_time();
-        let subscriber = FmtSubscriber::builder()
-            .with_max_level(Level::TRACE)
-            .with_writer(io::stderr)
-            .event_format(format)
-            .finish();
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("setting the default global subscriber should succeed");
+    let level = if args.quiet {
+        Level::ERROR
+    } else if args.verbose {
+        Level::TRACE
+    } else {
+        Level::INFO
+    };
+    // Follow https://no-color.org, and don't write escape codes to files.
+    let no_color = args.no_color
+        || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    if no_color {
+        owo_colors::set_override(false);
+    }
+    let format = fmt::format().with_target(false).without_time();
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .event_format(format)
+        .with_ansi(!no_color && io::stderr().is_terminal())
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("setting the default global subscriber should succeed");
+
+    if args.quiet && args.verbose {
+        error!("--quiet and --verbose can't be used together");
+        return ExitCode::FAILURE;
+    }
+
+    if args.notify {
+        if cfg!(feature = "notify") {
+            NOTIFY.store(true, Ordering::Relaxed);
+        } else {
+            warn!("ignoring --notify since chmi was built without the notify feature");
+        }
+    }
+
+    let command = match args.command {
+        // Cache commands don't need monitors, so handle them before getting
+        // monitors, which would populate the cache.
+        Some(Command::Cache(cache_args)) => {
+            if let Err(err) = cache(cache_args, args.cache_dir.as_deref()) {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+            return ExitCode::SUCCESS;
+        }
+        // Parsing capabilities strings doesn't touch monitors at all.
+        Some(Command::ParseCapabilities(parse_capabilities_args)) => {
+            if let Err(err) =
+                parse_capabilities(parse_capabilities_args, args.json)
+            {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+            return ExitCode::SUCCESS;
+        }
+        command => command,
+    };
+
+    let cache_mode = if args.no_cache {
+        CacheMode::Disabled
+    } else if args.refresh {
+        CacheMode::Refresh
+    } else {
+        CacheMode::Enabled
+    };
+    let mut options = Options {
+        cache_mode,
+        cache_dir: args.cache_dir.clone(),
+        ..Options::default()
+    };
+    if let Some(timeout_ms) = args.timeout_ms {
+        options.timeout = Duration::from_millis(timeout_ms);
     }
+    if let Some(capabilities_timeout_ms) = args.capabilities_timeout_ms {
+        options.capabilities_timeout =
+            Duration::from_millis(capabilities_timeout_ms);
+    }
+    if let Some(retries) = args.retries {
+        options.retries = retries;
+    }
+    options.strict = args.strict;
+
+    if args.here && args.monitor.is_some() {
+        error!("--here and --monitor can't be used together");
+        return ExitCode::FAILURE;
+    }
+
+    let config = Config::load().unwrap_or_else(|err| {
+        warn!("{:#}", err);
+        Config::default()
+    });
+    options.overrides = config.overrides.clone();
+
+    let command = match command {
+        // Watch commands get monitors whenever the displays change.
+        Some(Command::Watch(watch_args)) => {
+            if let Err(err) = watch(&options, &config, watch_args, args.force)
+            {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+            return ExitCode::SUCCESS;
+        }
+        // Diagnosing monitors can't rely on getting them, which skips the
+        // ones that fail.
+        Some(Command::Doctor(_)) => {
+            if let Err(err) = doctor(&options) {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+            return ExitCode::SUCCESS;
+        }
+        command => command,
+    };
+
+    let (monitor_arg, input_arg) = match &args.alias {
+        Some(_) if command.is_some() => {
+            error!("an alias can't be used with a subcommand");
+            return ExitCode::FAILURE;
+        }
+        Some(_)
+            if args.here || args.monitor.is_some() || args.input.is_some() =>
+        {
+            error!(
+                "an alias can't be used with --here, --monitor, or --input"
+            );
+            return ExitCode::FAILURE;
+        }
+        Some(name) => {
+            let Some(alias) = config.aliases.get(name) else {
+                error!("unable to find alias '{}'", name);
+                return ExitCode::FAILURE;
+            };
+            (Some(alias.display.clone()), Some(alias.input.clone()))
+        }
+        None => (args.monitor.clone(), args.input.clone()),
+    };
 
-    let mut monitors = match chmi::get_monitors() {
+    let monitors = if args.here {
+        chmi::get_current_monitor(&options).map(|monitor| vec![monitor])
+    } else {
+        chmi::get_monitors(&options)
+    };
+    let mut monitors = match monitors {
         Ok(monitors) => monitors,
         Err(err) => {
-            error!("{}", err);
+            report_error(&err, args.json);
             return ExitCode::FAILURE;
         }
     };
 
-    monitors.retain(|monitor| {
-        let has_input_select = monitor.capabilities().has_input_select();
-        if !has_input_select {
-            warn!(
-                "ignoring monitor '{}' since it doesn't support input select",
-                monitor.name()
-            );
+    // With --here, only the current monitor is available, so aliases for
+    // other monitors would be falsely reported as missing.
+    if !args.here {
+        for (name, alias) in &config.aliases {
+            let result = find_monitor_index(&monitors, &alias.display)
+                .and_then(|index| {
+                    monitors[index].capabilities().find_input(&alias.input)
+                });
+            if let Err(err) = result {
+                warn!("alias '{}' is invalid: {:#}", name, err);
+            }
         }
-        has_input_select
-    });
+    }
 
-    if monitors.is_empty() {
-        info!("unable to find a monitor, try `chmi --verbose` for more information");
-        // I count this as a success because the program didn't fail. It's
-        // likely a problem with the user's setup.
+    if let Some(display) = &args.show_capabilities {
+        let result =
+            find_monitor_index(&monitors, display).and_then(|index| {
+                print_capabilities(monitors[index].capabilities(), args.json)
+            });
+        match result {
+            Ok(()) => return ExitCode::SUCCESS,
+            Err(err) => {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(command) = command {
+        let result = match command {
+            Command::List(list_args) => list(&monitors, list_args, args.json),
+            Command::Get(get_args) => get(&mut monitors, get_args, args.json),
+            Command::Info(info_args) => {
+                info(&mut monitors, info_args, args.json)
+            }
+            Command::Cycle(cycle_args) => {
+                cycle(&mut monitors, cycle_args, args.dry_run, args.force)
+            }
+            Command::SetMany(set_many_args) => set_many(
+                &mut monitors,
+                set_many_args,
+                args.dry_run,
+                args.force,
+            ),
+            Command::Raw(raw_args) => {
+                raw(&mut monitors, raw_args, args.dry_run, args.force)
+            }
+            Command::Brightness(brightness_args) => {
+                brightness(&mut monitors, brightness_args, args.dry_run)
+            }
+            Command::Contrast(contrast_args) => {
+                contrast(&mut monitors, contrast_args, args.dry_run)
+            }
+            Command::Power(power_args) => {
+                power(&mut monitors, power_args, args.dry_run)
+            }
+            Command::Profile(profile_args) => {
+                profile(&mut monitors, profile_args, args.dry_run, args.force)
+            }
+            Command::Watch(_)
+            | Command::Doctor(_)
+            | Command::Cache(_)
+            | Command::ParseCapabilities(_) => {
+                unreachable!(
+                    "watch, doctor, cache, and parse-capabilities commands should already be handled"
+                )
+            }
+        };
+        if let Err(err) = result {
+            report_error(&err, args.json);
+            return ExitCode::FAILURE;
+        }
         return ExitCode::SUCCESS;
     }
 
-    let mut monitor_choices = Vec::new();
-    for (i, monitor) in monitors.iter().enumerate() {
-        monitor_choices.push(i + 1);
-        println!(
-            "  {} {}",
-            (i + 1).if_supports_color(Stdout, |text| text.bright_cyan()),
-            monitor.name()
-        );
+    let (mut monitors, unsupported_monitors): (Vec<_>, Vec<_>) = monitors
+        .into_iter()
+        .partition(|monitor| unsupported_reason(monitor.as_ref()).is_none());
+    // With --all, the monitor prompt says why they're left out instead.
+    if !args.all {
+        for monitor in &unsupported_monitors {
+            if let Some(reason) = unsupported_reason(monitor.as_ref()) {
+                warn!(
+                    "ignoring monitor '{}' since it {}",
+                    monitor.name(),
+                    reason
+                );
+            }
+        }
+    }
+
+    if monitors.is_empty() {
+        if args.all {
+            print_unsupported_monitors(&unsupported_monitors);
+        }
+        info!("unable to find a monitor, try `chmi --verbose` for more information");
+        // Use a distinct exit code so that scripts can tell this apart from
+        // a failure. It's likely a problem with the user's setup.
+        return ExitCode::from(NO_MONITORS_EXIT_CODE);
     }
 
-    let monitor_choice = get_choice("Monitor", &monitor_choices);
-    let monitor = &mut monitors[monitor_choice - 1];
+    let last_choice = LastChoice::load().unwrap_or_else(|err| {
+        warn!("{:#}", err);
+        None
+    });
+
+    let monitor_index = match &monitor_arg {
+        // The only monitor is the one the terminal is on.
+        None if args.here => 0,
+        Some(display) => match find_monitor_index(&monitors, display) {
+            Ok(index) => index,
+            Err(err) => {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            let mut monitor_choices = Vec::new();
+            for (i, monitor) in monitors.iter().enumerate() {
+                monitor_choices.push(i + 1);
+                println!(
+                    "  {} {}",
+                    (i + 1)
+                        .if_supports_color(Stdout, |text| text.bright_cyan()),
+                    monitor.name()
+                );
+            }
+            if args.all {
+                print_unsupported_monitors(&unsupported_monitors);
+            }
+
+            let default = last_choice
+                .as_ref()
+                .and_then(|last_choice| {
+                    monitors
+                        .iter()
+                        .position(|monitor| monitor.id() == last_choice.id)
+                })
+                .map(|i| i + 1);
+            get_choice("Monitor", &monitor_choices, default) - 1
+        }
+    };
+    let monitor = &mut monitors[monitor_index];
 
-    let curr_input = match monitor.input() {
-        Ok(input) => input,
+    let curr_value = match monitor.input() {
+        Ok(input) => u8::from(input),
         Err(err) => {
-            error!("{}", err);
+            report_error(&err, args.json);
             return ExitCode::FAILURE;
         }
     };
 
-    let inputs = monitor.capabilities().inputs().unwrap();
-
-    let mut input_choices = Vec::new();
-    for (i, input) in inputs.iter().enumerate() {
-        input_choices.push(i + 1);
-
-        if input == &curr_input {
-            println!(
-                "  {} {} (*)",
-                (i + 1).if_supports_color(Stdout, |text| text.bright_cyan()),
-                input
-            );
-        } else {
-            println!(
-                "  {} {}",
-                (i + 1).if_supports_color(Stdout, |text| text.bright_cyan()),
-                input
-            );
+    let value = match &input_arg {
+        Some(name) => match monitor.capabilities().find_input(name) {
+            Ok(input) => input.into(),
+            Err(err) => {
+                error!(
+                    "monitor '{}' doesn't support the input: {:#}",
+                    monitor.name(),
+                    err
+                );
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            // Only offer the last input as the default for the same monitor.
+            let default_value = last_choice
+                .filter(|last_choice| last_choice.id == monitor.id())
+                .map(|last_choice| last_choice.input);
+            prompt_input(monitor.capabilities(), curr_value, default_value)
         }
+    };
+
+    if args.dry_run {
+        println!(
+            "would set {} from {} to {}",
+            monitor.name(),
+            Input::from(curr_value),
+            Input::from(value)
+        );
+        return ExitCode::SUCCESS;
     }
 
-    let input_choice = get_choice("Input", &input_choices);
-    let input = &inputs[input_choice - 1];
+    let last_choice = LastChoice { id: monitor.id().to_owned(), input: value };
+    if let Err(err) = last_choice.save() {
+        warn!("{:#}", err);
+    }
 
-    if input == &curr_input {
+    if value == curr_value {
         return ExitCode::SUCCESS;
     }
 
-    if let Err(err) = monitor.set_input(*input) {
-        error!("{}", err);
+    // Switching the display the terminal is on can leave it blank, hiding
+    // the outcome, so switch back unless the switch is kept once the display
+    // comes back. Inputs passed with --input are left alone for scripts.
+    let revert_current_display = !args.force
+        && args.auto_revert.is_none()
+        && input_arg.is_none()
+        && chmi::is_current_monitor(monitor.as_ref());
+
+    // Auto-revert and reverting the current display already guard against
+    // stranding the only display. Inputs passed with --input aren't
+    // confirmed, as with other scripted switches.
+    if args.auto_revert.is_none() && !revert_current_display {
+        match confirm_only_display_switch(
+            monitor.as_ref(),
+            Input::from(value),
+            args.force,
+            input_arg.is_none(),
+        ) {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::SUCCESS,
+            Err(err) => {
+                report_error(&err, args.json);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if revert_current_display {
+        println!(
+            "switching this display, press Enter within {} s after it comes back to keep the change, otherwise it reverts",
+            CURRENT_DISPLAY_REVERT_TIMEOUT.as_secs()
+        );
+    }
+
+    if let Err(err) = set_input_value(monitor.as_mut(), value) {
+        report_error(&err, args.json);
         return ExitCode::FAILURE;
     }
 
+    // Read the input back since the screen might go dark if the new input
+    // has no signal, leaving no other sign that the switch worked.
+    match monitor.input() {
+        Ok(actual) if u8::from(actual) == value => {
+            let message = format!("switched {} to {}", monitor.name(), actual);
+            info!("{}", message);
+            notify(&message);
+        }
+        Ok(actual) => warn!(
+            "monitor '{}' reports {} instead of {}",
+            monitor.name(),
+            actual,
+            Input::from(value)
+        ),
+        Err(err) => warn!("{:#}", err),
+    }
+
+    let keep = if let Some(seconds) = args.auto_revert {
+        confirm_within("Keep this input?", Duration::from_secs(seconds))
+    } else if revert_current_display {
+        read_line_within(CURRENT_DISPLAY_REVERT_TIMEOUT).is_some()
+    } else {
+        true
+    };
+    if !keep {
+        info!(
+            "switching {} back to {}",
+            monitor.name(),
+            Input::from(curr_value)
+        );
+        if let Err(err) = set_input_value(monitor.as_mut(), curr_value) {
+            report_error(&err, args.json);
+            return ExitCode::FAILURE;
+        }
+    }
+
     ExitCode::SUCCESS
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vcp_code_with_prefix() {
+        assert_eq!(parse_vcp_code("10"), Ok(0x10));
+        assert_eq!(parse_vcp_code("0x10"), Ok(0x10));
+        assert_eq!(parse_vcp_code("0X10"), Ok(0x10));
+        assert!(parse_vcp_code("0x0x10").is_err());
+    }
+
+    #[test]
+    fn parse_vcp_value_with_prefix() {
+        assert_eq!(parse_vcp_value("1234"), Ok(0x1234));
+        assert_eq!(parse_vcp_value("0x1234"), Ok(0x1234));
+        assert_eq!(parse_vcp_value("0X1234"), Ok(0x1234));
+        assert!(parse_vcp_value("0x0x10").is_err());
+    }
+}
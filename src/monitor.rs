@@ -1,8 +1,227 @@
-use crate::cap::{Capabilities, Input};
+use anyhow::{anyhow, bail};
+use tracing::warn;
 
+use crate::{
+    cap::{
+        signed_vcp_value, Capabilities, Input, VcpType, BRIGHTNESS_CODE,
+        CONTRAST_CODE, INPUT_SELECT_CODE,
+    },
+    edid::Edid,
+};
+
+/// What [`Monitor::set_input_checked`] did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SetOutcome {
+    /// The monitor was already on the input, so it wasn't set.
+    Unchanged,
+    /// The monitor was switched from one input to another.
+    Changed { from: Input, to: Input },
+}
+
+/// Returns `percent` changed by `delta` percentage points, clamped between 0
+/// and 100.
+pub fn adjust_percent(percent: u8, delta: i16) -> u8 {
+    // The clamp makes the narrowing lossless.
+    i16::from(percent).saturating_add(delta).clamp(0, 100) as u8
+}
+
+/// A physical monitor that can be controlled over DDC/CI.
 pub trait Monitor {
+    /// Returns the friendly name of the monitor, e.g., "DELL U2720Q".
     fn name(&self) -> &str;
+    /// Returns the device ID of the monitor, e.g.,
+    /// `\\?\DISPLAY#GSM5B08#...`. Unlike the name, it's unique and stable
+    /// across reboots.
+    fn id(&self) -> &str;
+    /// Returns the details from the monitor's EDID, if it could be read.
+    fn edid(&self) -> Option<&Edid>;
+    /// Returns the serial number from the monitor's EDID, if it has one.
+    /// Unlike the name and ID, it stays the same when the monitor is moved to
+    /// another port.
+    fn serial(&self) -> Option<&str> {
+        self.edid().and_then(|edid| edid.serial.as_deref())
+    }
+    /// Returns the capabilities parsed from the monitor's capabilities string.
     fn capabilities(&self) -> &Capabilities;
+    /// Returns the VCP code the monitor switches inputs with, which is input
+    /// select unless the config file overrides it.
+    fn input_select_code(&self) -> u8 {
+        INPUT_SELECT_CODE
+    }
+    /// Returns the currently selected input.
     fn input(&self) -> anyhow::Result<Input>;
+    /// Returns whether the monitor lists `input` in its capabilities.
+    fn supports_input(&self, input: Input) -> bool {
+        self.capabilities()
+            .inputs()
+            .is_some_and(|inputs| inputs.contains(&input))
+    }
+    /// Switches to `input` and waits for the monitor to report it.
+    ///
+    /// # Errors
+    /// Returns `Err` if the monitor doesn't list `input` in its capabilities.
     fn set_input(&mut self, input: Input) -> anyhow::Result<()>;
+    /// Switches to `input` like [`Monitor::set_input`], unless the monitor is
+    /// already on it, and returns whether the input changed.
+    fn set_input_checked(
+        &mut self,
+        input: Input,
+    ) -> anyhow::Result<SetOutcome> {
+        let from = self.input()?;
+        if from == input {
+            return Ok(SetOutcome::Unchanged);
+        }
+
+        self.set_input(input)?;
+        Ok(SetOutcome::Changed { from, to: input })
+    }
+    /// Switches to the input with the raw MCCS value `value`.
+    ///
+    /// Unlike [`Monitor::set_input`], this accepts vendor-specific values and
+    /// doesn't wait for the monitor to report the new input.
+    fn set_input_by_raw(&mut self, value: u8) -> anyhow::Result<()> {
+        self.set_vcp(self.input_select_code(), u16::from(value))
+    }
+    /// Returns the current and maximum value of a VCP code, in that order.
+    fn vcp(&self, code: u8) -> anyhow::Result<(u16, u16)> {
+        let (_, current, maximum) = self.vcp_with_type(code)?;
+        Ok((current, maximum))
+    }
+    /// Returns the type, current value, and maximum value of a VCP code, in
+    /// that order. The type tells whether the code holds a value at all.
+    fn vcp_with_type(&self, code: u8) -> anyhow::Result<(VcpType, u16, u16)>;
+    /// Sets the value of a VCP code.
+    fn set_vcp(&mut self, code: u8, value: u16) -> anyhow::Result<()>;
+    /// Returns the current and maximum value of a VCP code as signed numbers.
+    /// See [`crate::is_signed_vcp_code`] for which codes are signed.
+    ///
+    /// # Errors
+    /// Returns `Err` if the code is unsigned and a value is above `i16::MAX`.
+    fn vcp_signed(&self, code: u8) -> anyhow::Result<(i16, i16)> {
+        let (current, maximum) = self.vcp(code)?;
+        let signed = |value| {
+            signed_vcp_value(code, value).ok_or_else(|| {
+                anyhow!(
+                    "value {} of VCP code {:#04X} doesn't fit in a signed 16-bit number",
+                    value,
+                    code
+                )
+            })
+        };
+        Ok((signed(current)?, signed(maximum)?))
+    }
+    /// Returns the code, current value, and maximum value of each VCP code
+    /// in the capabilities. Codes that can't be read are logged and skipped.
+    fn vcp_values(&self) -> anyhow::Result<Vec<(u8, u16, u16)>> {
+        let Some(vcp_codes) = &self.capabilities().vcp else {
+            return Ok(Vec::new());
+        };
+
+        let mut values = Vec::new();
+        for vcp_code in vcp_codes {
+            match self.vcp(vcp_code.code) {
+                Ok((current, maximum)) => {
+                    values.push((vcp_code.code, current, maximum))
+                }
+                Err(err) => warn!("{:#}", err),
+            }
+        }
+
+        Ok(values)
+    }
+    /// Returns the value of a continuous VCP code as a percentage of its
+    /// maximum value.
+    fn vcp_percent(&self, code: u8) -> anyhow::Result<u8> {
+        let (current, maximum) = self.vcp(code)?;
+        if maximum == 0 {
+            bail!(
+                "monitor '{}' reported a maximum of 0 for VCP code {:#04X}",
+                self.name(),
+                code
+            );
+        }
+
+        let percent = (u32::from(current) * 100 + u32::from(maximum) / 2)
+            / u32::from(maximum);
+        Ok(percent.min(100) as u8)
+    }
+    /// Sets a continuous VCP code to `percent` of its maximum value.
+    /// Percentages above 100 are clamped.
+    fn set_vcp_percent(
+        &mut self,
+        code: u8,
+        percent: u8,
+    ) -> anyhow::Result<()> {
+        let (_, maximum) = self.vcp(code)?;
+        let percent = u32::from(percent.min(100));
+        let value = (percent * u32::from(maximum) + 50) / 100;
+        self.set_vcp(code, value as u16)
+    }
+    /// Returns `Err` naming `feature`, e.g., "brightness", if the monitor
+    /// doesn't list `code` in its capabilities.
+    fn require_vcp_code(&self, code: u8, feature: &str) -> anyhow::Result<()> {
+        if !self.capabilities().has_vcp_code(code) {
+            bail!("monitor '{}' doesn't support {}", self.name(), feature);
+        }
+        Ok(())
+    }
+    /// Returns the brightness as a percentage of the maximum brightness.
+    ///
+    /// # Errors
+    /// Returns `Err` if the monitor doesn't list brightness in its
+    /// capabilities.
+    fn brightness(&self) -> anyhow::Result<u8> {
+        self.require_vcp_code(BRIGHTNESS_CODE, "brightness")?;
+        self.vcp_percent(BRIGHTNESS_CODE)
+    }
+    /// Sets the brightness to `percent` of the maximum brightness. Percentages
+    /// above 100 are clamped.
+    fn set_brightness(&mut self, percent: u8) -> anyhow::Result<()> {
+        self.require_vcp_code(BRIGHTNESS_CODE, "brightness")?;
+        self.set_vcp_percent(BRIGHTNESS_CODE, percent)
+    }
+    /// Returns the contrast as a percentage of the maximum contrast.
+    ///
+    /// # Errors
+    /// Returns `Err` if the monitor doesn't list contrast in its
+    /// capabilities.
+    fn contrast(&self) -> anyhow::Result<u8> {
+        self.require_vcp_code(CONTRAST_CODE, "contrast")?;
+        self.vcp_percent(CONTRAST_CODE)
+    }
+    /// Sets the contrast to `percent` of the maximum contrast. Percentages
+    /// above 100 are clamped.
+    fn set_contrast(&mut self, percent: u8) -> anyhow::Result<()> {
+        self.require_vcp_code(CONTRAST_CODE, "contrast")?;
+        self.set_vcp_percent(CONTRAST_CODE, percent)
+    }
+    /// Changes the brightness by `delta` percentage points, clamped between 0
+    /// and 100, and returns the new brightness. A `delta` that doesn't change
+    /// the brightness doesn't set it.
+    fn adjust_brightness(&mut self, delta: i16) -> anyhow::Result<u8> {
+        let current = self.brightness()?;
+        let percent = adjust_percent(current, delta);
+        if percent != current {
+            self.set_brightness(percent)?;
+        }
+        Ok(percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust_percent_clamps() {
+        assert_eq!(adjust_percent(50, 10), 60);
+        assert_eq!(adjust_percent(50, -60), 0);
+        assert_eq!(adjust_percent(50, 60), 100);
+    }
+
+    #[test]
+    fn adjust_percent_with_large_delta() {
+        assert_eq!(adjust_percent(50, i16::MAX), 100);
+        assert_eq!(adjust_percent(50, i16::MIN), 0);
+    }
 }
@@ -0,0 +1,62 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::config::Override;
+
+/// How the capabilities cache is used when getting monitors.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CacheMode {
+    /// Read capabilities strings from the cache, and write fetched
+    /// capabilities strings to it.
+    #[default]
+    Enabled,
+    /// Fetch every capabilities string, and overwrite the cached ones.
+    Refresh,
+    /// Fetch every capabilities string, and leave the cache untouched.
+    Disabled,
+}
+
+/// Options for getting monitors.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub cache_mode: CacheMode,
+    /// The directory to keep the capabilities cache in, instead of the
+    /// `CHMI_CACHE_DIR` directory or the default one.
+    pub cache_dir: Option<PathBuf>,
+    /// How long cached capabilities strings are used before being refetched.
+    pub cache_max_age: Duration,
+    /// How long to wait for a monitor to respond to a DDC/CI request before
+    /// giving up on it.
+    pub timeout: Duration,
+    /// How long to wait for a monitor's capabilities string, which some
+    /// monitors, e.g., TVs, take seconds to get ready. Empty and incomplete
+    /// capabilities strings are requested again until then.
+    pub capabilities_timeout: Duration,
+    /// How many times to retry a DDC/CI request that fails, e.g., because
+    /// the monitor is still waking up.
+    pub retries: u32,
+    /// How long to wait before the first retry. The delay doubles for each
+    /// retry after that.
+    pub retry_delay: Duration,
+    /// Inputs to use instead of the ones in the capabilities of the monitors
+    /// they match.
+    pub overrides: Vec<Override>,
+    /// Whether to fail if any monitor fails to respond, instead of logging
+    /// and skipping it.
+    pub strict: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            cache_mode: CacheMode::default(),
+            cache_dir: None,
+            cache_max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            timeout: Duration::from_secs(2),
+            capabilities_timeout: Duration::from_secs(6),
+            retries: 2,
+            retry_delay: Duration::from_millis(250),
+            overrides: Vec::new(),
+            strict: false,
+        }
+    }
+}
@@ -1,13 +1,13 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt, ops::Range};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use logos::Logos;
 
-use crate::cap::{Capabilities, VcpCode};
+use crate::cap::{Capabilities, VcpCode, INPUT_SELECT_CODE};
 
 #[derive(Clone, Copy, Debug, Logos, PartialEq)]
 #[logos(skip "[ \x00]")]
-enum Token {
+enum Token<'a> {
     #[token("(")]
     LeftParen,
     #[token(")")]
@@ -15,49 +15,149 @@ enum Token {
 
     #[token("vcp")]
     Vcp,
-    #[regex("[0-9A-F][0-9A-F]", |lex| u8::from_str_radix(lex.slice(), 16).unwrap())]
+    #[token("model")]
+    Model,
+    #[token("cmds")]
+    Cmds,
+    #[token("prot")]
+    Prot,
+    #[token("type")]
+    Type,
+    #[token("mccs_ver")]
+    MccsVer,
+    #[regex("[0-9A-Fa-f][0-9A-Fa-f]", |lex| u8::from_str_radix(lex.slice(), 16).unwrap())]
     HexNumber(u8),
 
-    #[regex("[a-zA-Z0-9_\\.]+")]
-    Unknown,
+    #[regex("[a-zA-Z0-9_\\.]+", |lex| lex.slice())]
+    Unknown(&'a str),
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match &self {
             Token::LeftParen => "'('",
             Token::RightParen => "')'",
             Token::Vcp => "'vcp'",
+            Token::Model => "'model'",
+            Token::Cmds => "'cmds'",
+            Token::Prot => "'prot'",
+            Token::Type => "'type'",
+            Token::MccsVer => "'mccs_ver'",
             Token::HexNumber(_) => "hexadecimal number",
-            Token::Unknown => "unknown",
+            Token::Unknown(_) => "unknown",
         };
         write!(f, "{}", s)
     }
 }
 
+/// The maximum number of tokens in a capabilities string. Real ones have a
+/// few hundred, so more means the string is garbage, e.g., from a flaky
+/// cable.
+const MAX_TOKENS: usize = 8192;
+/// The maximum nesting depth of groups. Real capabilities strings nest a few
+/// levels deep at most.
+const MAX_DEPTH: usize = 16;
+/// The number of characters shown on each side of the problem in a parse
+/// error.
+const SNIPPET_CONTEXT: usize = 20;
+
+/// Returns an error for a problem at byte `offset` of `source`, with a
+/// snippet of `source` and a caret pointing at the problem.
+fn error_at(
+    source: &str,
+    offset: usize,
+    message: impl fmt::Display,
+) -> anyhow::Error {
+    let start = source[..offset]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let end = source[offset..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map_or(source.len(), |(i, _)| offset + i);
+    let caret_column = source[start..offset].chars().count();
+    anyhow!(
+        "{} at offset {}\n  {}\n  {}^",
+        message,
+        offset,
+        &source[start..end],
+        " ".repeat(caret_column)
+    )
+}
+
 struct CapabilitiesStringParser<'a> {
-    tokens: &'a [Token],
+    source: &'a str,
+    tokens: &'a [Token<'a>],
+    /// The byte range of each token in `source`, for errors.
+    spans: &'a [Range<usize>],
     index: usize,
 }
 
 impl<'a> CapabilitiesStringParser<'a> {
-    fn new(tokens: &'a [Token]) -> CapabilitiesStringParser<'a> {
-        CapabilitiesStringParser { tokens, index: 0 }
+    fn new(
+        source: &'a str,
+        tokens: &'a [Token<'a>],
+        spans: &'a [Range<usize>],
+    ) -> CapabilitiesStringParser<'a> {
+        CapabilitiesStringParser { source, tokens, spans, index: 0 }
     }
 
     fn parse(&mut self) -> anyhow::Result<Capabilities> {
-        let mut capabilities = Capabilities { vcp: None };
+        let mut capabilities = Capabilities::default();
 
         self.expect(Token::LeftParen)?;
         while !self.check(Token::RightParen) {
             match self.next()? {
-                Token::Vcp => capabilities.vcp = Some(self.parse_vcp()?),
-                Token::Unknown => {
-                    self.expect(Token::LeftParen)?;
-                    self.eat_until(Token::RightParen);
-                    self.expect(Token::RightParen)?;
+                Token::Vcp => {
+                    let (vcp_codes, input_labels) = self.parse_vcp()?;
+                    capabilities.vcp = Some(vcp_codes);
+                    capabilities.input_labels = input_labels;
+                }
+                Token::Model => capabilities.model = Some(self.parse_model()?),
+                Token::Cmds => capabilities.commands = self.parse_cmds()?,
+                Token::Prot => {
+                    capabilities.protocol =
+                        Some(self.parse_text("protocol")?.to_ascii_lowercase())
+                }
+                // Monitors disagree on the case, e.g., `lcd` and `LCD`.
+                Token::Type => {
+                    capabilities.display_type = Some(
+                        self.parse_text("display type")?.to_ascii_lowercase(),
+                    )
+                }
+                Token::MccsVer => {
+                    capabilities.mccs_version =
+                        Some(self.parse_text("MCCS version")?)
+                }
+                Token::Unknown(s) => {
+                    // Some monitors don't use a model group, and instead put
+                    // a bare model or vendor name right before the cmds
+                    // group, e.g., `SAMSUNGcmds(...)`.
+                    if let Some(model) = s
+                        .strip_suffix("cmds")
+                        .filter(|model| !model.is_empty())
+                    {
+                        capabilities
+                            .model
+                            .get_or_insert_with(|| model.to_owned());
+                        capabilities.commands = self.parse_cmds()?;
+                        continue;
+                    }
+                    // Skip unknown tokens along with their group, if they
+                    // have one. Some tokens, e.g., `mswhql`, are sometimes
+                    // bare.
+                    if self.check(Token::LeftParen) {
+                        self.skip_group()?;
+                    }
+                }
+                token => {
+                    return Err(self.error(format_args!(
+                        "expected group name, found {}",
+                        token
+                    )))
                 }
-                _ => panic!("invalid capabilities string"),
             };
         }
         self.expect(Token::RightParen)?;
@@ -65,52 +165,135 @@ impl<'a> CapabilitiesStringParser<'a> {
         Ok(capabilities)
     }
 
-    fn parse_vcp(&mut self) -> anyhow::Result<Vec<VcpCode>> {
+    /// Parses the VCP codes along with the labels of the input select
+    /// values, if the monitor gives any.
+    fn parse_vcp(
+        &mut self,
+    ) -> anyhow::Result<(Vec<VcpCode>, BTreeMap<u8, String>)> {
         self.expect(Token::LeftParen)?;
         let mut vcp_codes = Vec::new();
+        let mut input_labels = BTreeMap::new();
         while !self.check(Token::RightParen) {
-            let vcp_code = self.parse_vcp_code()?;
+            let (vcp_code, labels) = self.parse_vcp_code()?;
+            if vcp_code.code == INPUT_SELECT_CODE {
+                input_labels = labels;
+            }
             vcp_codes.push(vcp_code)
         }
         self.expect(Token::RightParen)?;
-        Ok(vcp_codes)
+        Ok((vcp_codes, input_labels))
     }
 
-    fn parse_vcp_code(&mut self) -> anyhow::Result<VcpCode> {
+    fn parse_vcp_code(
+        &mut self,
+    ) -> anyhow::Result<(VcpCode, BTreeMap<u8, String>)> {
         let code = self.parse_number()?;
         let mut values = Vec::new();
+        let mut labels = BTreeMap::new();
         if self.eat(Token::LeftParen) {
             while !self.check(Token::RightParen) {
+                // Some monitors describe values with nested groups, e.g.,
+                // `11(PC)`. Keep the ones that label the previous value and
+                // skip the rest, which chmi doesn't use.
+                if self.check(Token::LeftParen) {
+                    match self.parse_label() {
+                        Some(label) => {
+                            if let Some(&value) = values.last() {
+                                labels.insert(value, label);
+                            }
+                        }
+                        None => self.skip_group()?,
+                    }
+                    continue;
+                }
                 let value = self.parse_number()?;
                 values.push(value);
             }
             self.expect(Token::RightParen)?;
         };
-        Ok(VcpCode { code, values })
+        Ok((VcpCode { code, values }, labels))
+    }
+
+    /// Parses a group of words labeling a value, e.g., `(PC)`, or returns
+    /// `None` without consuming anything if the next group isn't a label.
+    ///
+    /// Groups of only hexadecimal numbers, e.g., `(00 01)`, are sub-values
+    /// rather than labels.
+    fn parse_label(&mut self) -> Option<String> {
+        let start = self.index;
+        if let Ok(label) = self.parse_text("label") {
+            let has_word = self.tokens[start..self.index]
+                .iter()
+                .any(|token| matches!(token, Token::Unknown(_)));
+            if has_word {
+                return Some(label);
+            }
+        }
+        self.index = start;
+        None
+    }
+
+    fn parse_cmds(&mut self) -> anyhow::Result<Vec<u8>> {
+        self.expect(Token::LeftParen)?;
+        let mut commands = Vec::new();
+        while !self.check(Token::RightParen) {
+            let command = self.parse_number()?;
+            commands.push(command);
+        }
+        self.expect(Token::RightParen)?;
+        Ok(commands)
+    }
+
+    fn parse_model(&mut self) -> anyhow::Result<String> {
+        self.parse_text("model name")
+    }
+
+    /// Parses a group of words, e.g., `(LCD24)`, where `what` describes the
+    /// words for errors.
+    fn parse_text(&mut self, what: &str) -> anyhow::Result<String> {
+        self.expect(Token::LeftParen)?;
+        let mut words = Vec::new();
+        while !self.check(Token::RightParen) {
+            match self.next()? {
+                Token::Unknown(s) => words.push(s.to_owned()),
+                // Words that look like hexadecimal numbers, e.g., `AB`, are
+                // lexed as numbers, so format them back into text.
+                Token::HexNumber(n) => words.push(format!("{:02X}", n)),
+                token => {
+                    return Err(self.error(format_args!(
+                        "expected {}, found {}",
+                        what, token
+                    )))
+                }
+            }
+        }
+        self.expect(Token::RightParen)?;
+        Ok(words.join(" "))
     }
 
     fn parse_number(&mut self) -> anyhow::Result<u8> {
         match self.next()? {
             Token::HexNumber(n) => Ok(n),
-            token => {
-                Err(anyhow!("expected hexadecimal number, found {}", token))
-            }
+            token => Err(self.error(format_args!(
+                "expected hexadecimal number, found {}",
+                token
+            ))),
         }
     }
 
     /// Consumes and expects `token`.
-    fn expect(&mut self, token: Token) -> anyhow::Result<()> {
+    fn expect(&mut self, token: Token<'a>) -> anyhow::Result<()> {
         let t = self.next()?;
         if t == token {
             Ok(())
         } else {
-            Err(anyhow!("expected {}, found {}", token, t))
+            Err(self.error(format_args!("expected {}, found {}", token, t)))
         }
     }
 
     /// Consumes the next token if it's `token`, and returns whether the token
     /// was consumed.
-    fn eat(&mut self, token: Token) -> bool {
+    fn eat(&mut self, token: Token<'a>) -> bool {
         self.check(token)
             .then(|| {
                 self.index += 1;
@@ -118,45 +301,110 @@ impl<'a> CapabilitiesStringParser<'a> {
             .is_some()
     }
 
-    /// Consumes tokens until the next token is `token`.
-    fn eat_until(&mut self, token: Token) {
-        while self.index < self.tokens.len() && !self.check(token) {
-            self.index += 1;
+    /// Consumes a parenthesized group, including any nested groups.
+    fn skip_group(&mut self) -> anyhow::Result<()> {
+        self.expect(Token::LeftParen)?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::LeftParen if depth == MAX_DEPTH => {
+                    return Err(self.error(format_args!(
+                        "groups are nested more than {} deep",
+                        MAX_DEPTH
+                    )))
+                }
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                _ => {}
+            }
         }
+        Ok(())
     }
 
     /// Returns true if the next token is `token`.
-    fn check(&self, token: Token) -> bool {
+    fn check(&self, token: Token<'a>) -> bool {
         self.tokens.get(self.index).is_some_and(|&t| t == token)
     }
 
     /// Returns the next token.
-    fn next(&mut self) -> anyhow::Result<Token> {
+    fn next(&mut self) -> anyhow::Result<Token<'a>> {
         self.tokens
             .get(self.index)
             .map(|t| {
                 self.index += 1;
                 *t
             })
-            .ok_or(anyhow!("unexpected end-of-file"))
+            .ok_or_else(|| {
+                error_at(
+                    self.source,
+                    self.source.len(),
+                    "unexpected end-of-file",
+                )
+            })
     }
+
+    /// Returns an error for a problem with the last consumed token.
+    fn error(&self, message: impl fmt::Display) -> anyhow::Error {
+        let offset =
+            self.index.checked_sub(1).map_or(0, |i| self.spans[i].start);
+        error_at(self.source, offset, message)
+    }
+}
+
+/// Returns whether a capabilities string looks complete, i.e., its outer
+/// group is closed, rather than a partial reply. Like [`parse`], anything
+/// after the outer group is ignored.
+pub fn is_complete(capabilities_string: &str) -> bool {
+    let capabilities_string = capabilities_string.trim_start();
+    if !capabilities_string.starts_with('(') {
+        return false;
+    }
+
+    let mut depth = 0;
+    for c in capabilities_string.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
 }
 
 pub fn parse(capabilities_string: &str) -> anyhow::Result<Capabilities> {
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
     for (token, span) in Token::lexer(capabilities_string).spanned() {
         match token {
-            Ok(token) => tokens.push(token),
+            Ok(_) if tokens.len() == MAX_TOKENS => {
+                bail!(
+                    "capabilities string has more than {} tokens",
+                    MAX_TOKENS
+                )
+            }
+            Ok(token) => {
+                tokens.push(token);
+                spans.push(span);
+            }
             Err(_) => {
-                return Err(anyhow::anyhow!(
-                    "unexpected character(s) '{}'",
-                    &capabilities_string[span.start..span.end],
+                return Err(error_at(
+                    capabilities_string,
+                    span.start,
+                    format_args!(
+                        "unexpected character(s) '{}'",
+                        &capabilities_string[span.clone()],
+                    ),
                 ))
             }
         }
     }
 
-    CapabilitiesStringParser::new(&tokens)
+    CapabilitiesStringParser::new(capabilities_string, &tokens, &spans)
         .parse()
         .context("failed to parse capabilities string")
 }
@@ -164,6 +412,7 @@ pub fn parse(capabilities_string: &str) -> anyhow::Result<Capabilities> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cap::Input;
 
     #[test]
     fn parse_un880_capabilities() {
@@ -185,4 +434,167 @@ mod tests {
         let capabilities = parse(&capabilities_string).unwrap();
         insta::assert_debug_snapshot!(capabilities);
     }
+
+    #[test]
+    fn parse_protocol_and_display_type() {
+        let capabilities_string = "(prot(monitor) type(LCD)model(VG259) cmds(01 02 03 07 0C F3) vcp(10 60(11 12 0F)))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(capabilities.protocol.as_deref(), Some("monitor"));
+        assert_eq!(capabilities.display_type.as_deref(), Some("lcd"));
+
+        let capabilities_string =
+            "(prot(monitor)type(lcd)UN880cmds(01 02 03)vcp(10 60(11 12)))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(capabilities.protocol.as_deref(), Some("monitor"));
+        assert_eq!(capabilities.display_type.as_deref(), Some("lcd"));
+    }
+
+    #[test]
+    fn parse_mccs_version() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)vcp(10 60(11 12))mccs_ver(2.2)mswhql(1))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(capabilities.mccs_version.as_deref(), Some("2.2"));
+    }
+
+    #[test]
+    fn vg259_values_for() {
+        let capabilities_string = "(prot(monitor) type(LCD)model(VG259) cmds(01 02 03 07 0C F3) vcp(02 04 05 08 10 12 14(05 06 08 0B) 16 18 1A 52 60(11 12 0F) 62 6C 6E 70 86(02 0B) 87(00 0A 14 1E 28 32 3C 46 50 5A 64) 8A 8D(01 02) AC AE B6 C6 C8 C9 CC(01 02 03 04 05 06 07 08 09 0A 0C 0D 11 12 14 1A 1E 1F 23 30 31) D6(01 05) DC(01 02 03 04 05 06 07 08) DF E0(00 01 02 03 04 05) E1(00 01) E3(00 01 02 03 04 05 06) E4(00 01 02 03 04 05) E5(00 01 02 03) E6(00 01 02 03 04) E7(00 01) E9(00 01) EA(00 01) EB(00 01))mccs_ver(2.2)asset_eep(32)mpu(01)mswhql(1))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(
+            capabilities.values_for(0x60),
+            Some([0x11, 0x12, 0x0F].as_slice())
+        );
+        assert_eq!(
+            capabilities.values_for(0xD6),
+            Some([0x01, 0x05].as_slice())
+        );
+        assert_eq!(capabilities.values_for(0x10), Some([].as_slice()));
+        assert_eq!(capabilities.values_for(0xD0), None);
+    }
+
+    #[test]
+    fn parse_missing_vcp_capabilities() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)model(LCD24)cmds(01 02 03)mccs_ver(2.1))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert!(!capabilities.has_input_select());
+        assert_eq!(capabilities.inputs(), None);
+        insta::assert_debug_snapshot!(capabilities);
+    }
+
+    #[test]
+    fn parse_empty_vcp_capabilities() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)model(LCD24)cmds(01 02 03)vcp()mccs_ver(2.1))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert!(!capabilities.has_input_select());
+        assert_eq!(capabilities.inputs(), None);
+        insta::assert_debug_snapshot!(capabilities);
+    }
+
+    #[test]
+    fn parse_input_select_without_values() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)cmds(01 02 03)vcp(10 60 D6(01 04)))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert!(capabilities.has_input_select());
+        assert_eq!(capabilities.inputs(), Some(vec![]));
+    }
+
+    #[test]
+    fn parse_lowercase_hex_capabilities() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)cmds(01 0c f3)vcp(02 10 60(0f 11 12)))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(capabilities.commands, vec![0x01, 0x0C, 0xF3]);
+        assert_eq!(
+            capabilities.inputs(),
+            Some(vec![Input::DisplayPort1, Input::Hdmi1, Input::Hdmi2])
+        );
+    }
+
+    #[test]
+    fn parse_bare_and_nested_groups() {
+        let capabilities_string = "(prot(txt)type(lcd)mswhql vcp(10 60(11 12(00 (01)) 0F))info(a(b(c)) d)model(TV))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(
+            capabilities.inputs(),
+            Some(vec![Input::Hdmi1, Input::Hdmi2, Input::DisplayPort1])
+        );
+        assert!(capabilities.input_labels.is_empty());
+        assert_eq!(capabilities.model.as_deref(), Some("TV"));
+    }
+
+    #[test]
+    fn parse_input_labels() {
+        let capabilities_string =
+            "(prot(monitor)type(lcd)vcp(10 60(11(PC) 12(Game Console) 0F)))";
+        let capabilities = parse(capabilities_string).unwrap();
+        assert_eq!(
+            capabilities.inputs(),
+            Some(vec![Input::Hdmi1, Input::Hdmi2, Input::DisplayPort1])
+        );
+        assert_eq!(capabilities.input_label(Input::Hdmi1), Some("PC"));
+        assert_eq!(
+            capabilities.input_label(Input::Hdmi2),
+            Some("Game Console")
+        );
+        assert_eq!(capabilities.input_label(Input::DisplayPort1), None);
+    }
+
+    #[test]
+    fn parse_unexpected_token() {
+        let capabilities_string = "(prot(monitor)0F)";
+        let err = parse(capabilities_string).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            concat!(
+                "failed to parse capabilities string: ",
+                "expected group name, found hexadecimal number at offset 14\n",
+                "  (prot(monitor)0F)\n",
+                "                ^",
+            )
+        );
+    }
+
+    #[test]
+    fn parse_error_snippet_is_around_offset() {
+        let capabilities_string =
+            format!("(vcp({}GG){})", "10 ".repeat(10), "cmds(01)".repeat(5));
+        let err = parse(&capabilities_string).unwrap_err();
+        assert!(format!("{:#}", err).ends_with(concat!(
+            "at offset 35\n",
+            "  0 10 10 10 10 10 10 GG)cmds(01)cmds(01)c\n",
+            "                      ^",
+        )));
+    }
+
+    #[test]
+    fn complete_capabilities_strings() {
+        assert!(is_complete("(prot(monitor)vcp(10 60(11 12)))"));
+        assert!(!is_complete("(prot(monitor)vcp(10 60(11"));
+        assert!(!is_complete(""));
+        assert!(!is_complete("prot(monitor))"));
+        assert!(is_complete("(prot(monitor)vcp(10))mswhql(1))"));
+    }
+
+    #[test]
+    fn parse_unterminated_group() {
+        let capabilities_string = "(prot(monitor)info(a(b)";
+        assert!(parse(capabilities_string).is_err());
+    }
+
+    #[test]
+    fn parse_deeply_nested_groups() {
+        let capabilities_string =
+            format!("(info{}{})", "(".repeat(3000), ")".repeat(3000));
+        assert!(parse(&capabilities_string).is_err());
+    }
+
+    #[test]
+    fn parse_too_many_tokens() {
+        let capabilities_string = format!("(vcp({}))", "10 ".repeat(10000));
+        assert!(parse(&capabilities_string).is_err());
+    }
 }
@@ -0,0 +1,109 @@
+use std::{fs, io, path::PathBuf};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::monitor::Monitor;
+
+/// A monitor's settings in a profile.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProfileMonitor {
+    /// The device ID of the monitor.
+    pub id: String,
+    /// The EDID serial number of the monitor, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// The name of the monitor when the profile was saved, for messages.
+    pub name: String,
+    /// The raw value of the input.
+    pub input: u8,
+    /// The brightness as a percentage, if it was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    /// The contrast as a percentage, if it was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contrast: Option<u8>,
+}
+
+impl ProfileMonitor {
+    /// Returns whether the settings are for `monitor`.
+    ///
+    /// Monitors are matched by serial number if both have one, since it
+    /// stays the same when the monitor is moved to another port, and by
+    /// device ID otherwise.
+    pub fn matches(&self, monitor: &dyn Monitor) -> bool {
+        match (&self.serial, monitor.serial()) {
+            (Some(serial), Some(monitor_serial)) => serial == monitor_serial,
+            _ => self.id == monitor.id(),
+        }
+    }
+}
+
+/// The inputs, and optionally the brightness and contrast, of a set of
+/// monitors, saved under a name to be applied later.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Profile {
+    pub monitors: Vec<ProfileMonitor>,
+}
+
+impl Profile {
+    /// Reads the profile named `name`.
+    ///
+    /// # Errors
+    /// Returns `Err` if there's no profile named `name`.
+    pub fn load(name: &str) -> anyhow::Result<Profile> {
+        let profile_path = profile_path(name)?;
+
+        let contents = match fs::read_to_string(&profile_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                bail!("unable to find profile '{}'", name)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to read {}", profile_path.display())
+                })
+            }
+        };
+
+        serde_json::from_str(&contents).with_context(|| {
+            format!("failed to parse {}", profile_path.display())
+        })
+    }
+
+    /// Saves the profile as `name`, replacing any profile with the name.
+    pub fn save(&self, name: &str) -> anyhow::Result<()> {
+        let profile_path = profile_path(name)?;
+        if let Some(profiles_dir) = profile_path.parent() {
+            fs::create_dir_all(profiles_dir)
+                .context("failed to create the profiles directory")?;
+        }
+
+        fs::write(&profile_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| {
+                format!("failed to write {}", profile_path.display())
+            })
+    }
+}
+
+/// Returns the path of the profile named `name` in the data directory.
+///
+/// # Errors
+/// Returns `Err` if `name` isn't usable as a file name.
+fn profile_path(name: &str) -> anyhow::Result<PathBuf> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        bail!(
+            "invalid profile name '{}', use letters, numbers, '-', and '_'",
+            name
+        );
+    }
+
+    Ok(crate::project_dirs()?
+        .data_dir()
+        .join("profiles")
+        .join(format!("{}.json", name)))
+}
@@ -0,0 +1,50 @@
+use std::{fs, io};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The monitor and input chosen in the last run, offered as the defaults in
+/// the next run.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LastChoice {
+    /// The device ID of the monitor.
+    pub id: String,
+    /// The raw value of the input.
+    pub input: u8,
+}
+
+impl LastChoice {
+    /// Reads the last choice, or returns `None` if there isn't one.
+    pub fn load() -> anyhow::Result<Option<LastChoice>> {
+        let state_path =
+            crate::project_dirs()?.data_dir().join("last_choice.json");
+
+        let contents = match fs::read_to_string(&state_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to read {}", state_path.display())
+                })
+            }
+        };
+
+        serde_json::from_str(&contents).map(Some).with_context(|| {
+            format!("failed to parse {}", state_path.display())
+        })
+    }
+
+    /// Saves the choice for the next run.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let project_dirs = crate::project_dirs()?;
+        fs::create_dir_all(project_dirs.data_dir())
+            .context("failed to create the data directory")?;
+
+        let state_path = project_dirs.data_dir().join("last_choice.json");
+        fs::write(&state_path, serde_json::to_string(self)?).with_context(
+            || format!("failed to write {}", state_path.display()),
+        )
+    }
+}
@@ -1,15 +1,21 @@
 use std::{
+    borrow::Cow,
+    cell::Cell,
     collections::HashMap,
+    error::Error,
     ffi::{CStr, OsString},
-    mem,
+    fmt, mem,
     os::windows::ffi::OsStringExt,
-    ptr, slice,
+    panic, ptr, slice,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context};
-use tracing::error;
+use tracing::{debug, error, field, info_span, warn, Span};
 use windows::{
-    core::PCSTR,
+    core::{w, HRESULT, HSTRING, PCSTR},
     Win32::{
         Devices::Display::{
             CapabilitiesRequestAndCapabilitiesReply, DestroyPhysicalMonitor,
@@ -19,61 +25,259 @@ use windows::{
             GetPhysicalMonitorsFromHMONITOR, GetVCPFeatureAndVCPFeatureReply,
             QueryDisplayConfig, SetVCPFeature,
             DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
-            DISPLAYCONFIG_TARGET_DEVICE_NAME, PHYSICAL_MONITOR,
+            DISPLAYCONFIG_TARGET_DEVICE_NAME, MC_VCP_CODE_TYPE,
+            PHYSICAL_MONITOR,
+        },
+        Foundation::{
+            BOOL, ERROR_GRAPHICS_I2C_DEVICE_DOES_NOT_EXIST,
+            ERROR_GRAPHICS_I2C_ERROR_RECEIVING_DATA,
+            ERROR_GRAPHICS_I2C_ERROR_TRANSMITTING_DATA,
+            ERROR_GRAPHICS_I2C_NOT_SUPPORTED,
+            ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS,
+            ERROR_INSUFFICIENT_BUFFER, HANDLE, HINSTANCE, HWND, LPARAM,
+            LRESULT, RECT, TRUE, WPARAM,
         },
-        Foundation::{BOOL, FALSE, HANDLE, LPARAM, RECT, TRUE},
         Graphics::Gdi::{
             EnumDisplayDevicesA, EnumDisplayMonitors, GetMonitorInfoA,
-            DISPLAY_DEVICEA, HDC, HMONITOR, MONITORINFOEXA,
+            MonitorFromWindow, DISPLAY_DEVICEA, HDC, HMONITOR, MONITORINFOEXA,
+            MONITOR_DEFAULTTONEAREST,
+        },
+        System::{
+            Console::GetConsoleWindow,
+            LibraryLoader::GetModuleHandleW,
+            Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY},
+        },
+        UI::WindowsAndMessaging::{
+            CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW,
+            GetSystemMetrics, PeekMessageW, PostMessageW, RegisterClassW,
+            TranslateMessage, MSG, PM_REMOVE, SM_CMONITORS, WINDOW_EX_STYLE,
+            WM_APP, WM_DEVICECHANGE, WM_DISPLAYCHANGE, WNDCLASSW,
+            WS_OVERLAPPED,
         },
     },
 };
 
 use crate::{
     cache::CapabilitiesCache,
-    cap::{Capabilities, Input, INPUT_SELECT_CODE},
-    monitor, parse,
+    cap::{self, Capabilities, Input, VcpType, INPUT_SELECT_CODE},
+    diagnosis::{
+        CapabilitiesSource, Check, DisplayDiagnosis, MonitorDiagnosis,
+    },
+    edid::Edid,
+    monitor,
+    options::{CacheMode, Options},
+    parse,
 };
 
+/// The number of times to query the display configuration if it changes
+/// while querying it.
+const QUERY_DISPLAY_CONFIG_ATTEMPTS: u32 = 3;
+/// The number of times to read back the input of a monitor after setting it.
+const SET_INPUT_VERIFY_ATTEMPTS: u32 = 5;
+/// The delay before each read back of the input of a monitor.
+const SET_INPUT_VERIFY_DELAY: Duration = Duration::from_millis(200);
+/// The window message posted when the displays change.
+const DISPLAY_CHANGE_MESSAGE: u32 = WM_APP;
+/// How long to wait before requesting an empty or incomplete capabilities
+/// string again.
+const CAPABILITIES_REQUEST_DELAY: Duration = Duration::from_millis(500);
+/// The delay before handling display changes. Changes come in bursts, and
+/// monitors need a moment after being connected before they respond to
+/// DDC/CI.
+const DISPLAY_CHANGE_DELAY: Duration = Duration::from_secs(2);
+
+/// Calls `f` until it succeeds or the retries run out, backing off between
+/// attempts. Timeouts aren't retried since the monitor is unresponsive.
+fn retry<T>(
+    options: &Options,
+    mut f: impl FnMut() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut attempt = 1;
+    let mut delay = options.retry_delay;
+    loop {
+        match f() {
+            Err(err)
+                if attempt <= options.retries && !err.is::<TimedOut>() =>
+            {
+                warn!(
+                    "{:#}, retrying in {} ms ({}/{})",
+                    err,
+                    delay.as_millis(),
+                    attempt,
+                    options.retries
+                );
+                thread::sleep(delay);
+                attempt += 1;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A monitor handle, i.e., an HMONITOR or physical monitor handle, that can
+/// be moved to a worker thread.
+#[derive(Clone, Copy)]
+struct SendHandle<T>(T);
+
+// SAFETY: Monitor handles aren't tied to the thread that created them.
+unsafe impl<T> Send for SendHandle<T> {}
+
+impl<T: Copy> SendHandle<T> {
+    fn get(&self) -> T {
+        self.0
+    }
+}
+
+/// The error for a monitor that didn't respond to a DDC/CI request in time.
+#[derive(Debug)]
+struct TimedOut {
+    name: String,
+    timeout: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "monitor '{}' didn't respond within {} ms",
+            self.name,
+            self.timeout.as_millis()
+        )
+    }
+}
+
+impl Error for TimedOut {}
+
+/// The hint for DDC/CI errors that a sleeping monitor causes.
+const ASLEEP_HINT: &str = "the monitor may be asleep, wake it and retry";
+/// The hint for DDC/CI errors that a monitor with DDC/CI turned off causes.
+const DDC_CI_DISABLED_HINT: &str =
+    "enable DDC/CI in the monitor's OSD settings";
+
+/// The likely causes of the errors the Monitor Configuration API sets when
+/// DDC/CI requests fail. A sleeping monitor stops answering mid-request,
+/// while one with DDC/CI turned off doesn't answer at all. Drivers don't
+/// always report them the same way, so these are hints, not diagnoses.
+const DDC_CI_ERROR_HINTS: [(HRESULT, &str); 5] = [
+    (ERROR_GRAPHICS_I2C_ERROR_TRANSMITTING_DATA, ASLEEP_HINT),
+    (ERROR_GRAPHICS_I2C_ERROR_RECEIVING_DATA, ASLEEP_HINT),
+    (ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS, ASLEEP_HINT),
+    (ERROR_GRAPHICS_I2C_NOT_SUPPORTED, DDC_CI_DISABLED_HINT),
+    (ERROR_GRAPHICS_I2C_DEVICE_DOES_NOT_EXIST, DDC_CI_DISABLED_HINT),
+];
+
+/// Converts the error of a failed DDC/CI request, adding a hint about what
+/// to do if its code says why it failed.
+fn ddc_ci_error(err: windows::core::Error) -> anyhow::Error {
+    let hint = DDC_CI_ERROR_HINTS
+        .iter()
+        .find(|&&(code, _)| code == err.code())
+        .map(|&(_, hint)| hint);
+    match hint {
+        Some(hint) => anyhow::Error::new(err).context(hint),
+        None => err.into(),
+    }
+}
+
+/// Returns whether `err` was caused by a failed Windows API call.
+pub fn is_os_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<windows::core::Error>())
+}
+
+/// Runs `f` on a worker thread and waits up to `timeout` for it to finish.
+/// If it doesn't finish in time, the worker thread is abandoned.
+///
+/// # Errors
+/// Returns `Err` if `f` doesn't finish in time or panics.
+fn run_with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, RecvTimeoutError> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone if the wait timed out, so ignore the error.
+        let _ = sender.send(f());
+    });
+
+    receiver.recv_timeout(timeout)
+}
+
+/// Returns the part of a fixed-size string buffer before the first null
+/// character, or the whole buffer if it isn't null-terminated.
+fn until_nul<T: Default + PartialEq>(buffer: &[T]) -> &[T] {
+    let len =
+        buffer.iter().position(|c| *c == T::default()).unwrap_or(buffer.len());
+    &buffer[..len]
+}
+
+// The string conversions below replace invalid characters rather than
+// failing, since the strings are only used for display and lookups.
+
 fn string_from_wide(wide: &[u16]) -> String {
-    let len = wide.iter().position(|&c| c == 0).unwrap_or(0);
-    OsString::from_wide(&wide[..len])
-        .to_str()
-        .expect("WCHAR strings from the OS should be valid Unicode")
-        .to_string()
+    OsString::from_wide(until_nul(wide)).into_string().unwrap_or_else(
+        |os_string| {
+            let string = os_string.to_string_lossy().into_owned();
+            warn!("replaced invalid UTF-16 in '{}'", string);
+            string
+        },
+    )
+}
+
+fn string_from_bytes(bytes: &[u8]) -> String {
+    match String::from_utf8_lossy(until_nul(bytes)) {
+        Cow::Borrowed(string) => string.to_owned(),
+        Cow::Owned(string) => {
+            warn!("replaced invalid UTF-8 in '{}'", string);
+            string
+        }
+    }
 }
 
 /// Returns a map of device IDs to friendly names for all display devices.
 fn get_friendly_name_map() -> anyhow::Result<HashMap<String, String>> {
     unsafe {
-        let mut num_paths = 0;
-        let mut num_modes = 0;
-        GetDisplayConfigBufferSizes(
-            windows::Win32::Devices::Display::QDC_ONLY_ACTIVE_PATHS,
-            ptr::addr_of_mut!(num_paths),
-            ptr::addr_of_mut!(num_modes),
-        )
-        .ok()
-        .context(
-            "failed to get buffer sizes for display device configurations",
-        )?;
+        let mut attempt = 1;
+        let paths = loop {
+            let mut num_paths = 0;
+            let mut num_modes = 0;
+            GetDisplayConfigBufferSizes(
+                windows::Win32::Devices::Display::QDC_ONLY_ACTIVE_PATHS,
+                ptr::addr_of_mut!(num_paths),
+                ptr::addr_of_mut!(num_modes),
+            )
+            .ok()
+            .context(
+                "failed to get buffer sizes for display device configurations",
+            )?;
 
-        let mut paths = Vec::with_capacity(num_paths as usize);
-        let mut modes = Vec::with_capacity(num_modes as usize);
+            let mut paths = Vec::with_capacity(num_paths as usize);
+            let mut modes = Vec::with_capacity(num_modes as usize);
 
-        QueryDisplayConfig(
-            windows::Win32::Devices::Display::QDC_ONLY_ACTIVE_PATHS,
-            ptr::addr_of_mut!(num_paths),
-            paths.as_mut_ptr(),
-            ptr::addr_of_mut!(num_modes),
-            modes.as_mut_ptr(),
-            None,
-        )
-        .ok()
-        .context("failed to get display device configurations")?;
+            let result = QueryDisplayConfig(
+                windows::Win32::Devices::Display::QDC_ONLY_ACTIVE_PATHS,
+                ptr::addr_of_mut!(num_paths),
+                paths.as_mut_ptr(),
+                ptr::addr_of_mut!(num_modes),
+                modes.as_mut_ptr(),
+                None,
+            );
+            // The buffers are too small if a display was connected since
+            // getting their sizes, so get the new sizes and try again.
+            if result == ERROR_INSUFFICIENT_BUFFER
+                && attempt < QUERY_DISPLAY_CONFIG_ATTEMPTS
+            {
+                debug!("display configuration changed while querying it");
+                attempt += 1;
+                continue;
+            }
+            result
+                .ok()
+                .context("failed to get display device configurations")?;
 
-        paths.set_len(num_paths as usize);
-        modes.set_len(num_modes as usize);
+            paths.set_len(num_paths as usize);
+            break paths;
+        };
 
         let mut map = HashMap::new();
 
@@ -84,14 +288,18 @@ fn get_friendly_name_map() -> anyhow::Result<HashMap<String, String>> {
             target.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME;
             target.header.size = mem::size_of_val(&target) as u32;
 
+            // A display that fails, e.g., because it's being disconnected,
+            // shouldn't keep the other displays from being named.
+            let result =
+                DisplayConfigGetDeviceInfo(ptr::addr_of_mut!(target.header));
             // TODO: Use the actual success error code instead of hardcoding
             // its value.
-            if DisplayConfigGetDeviceInfo(ptr::addr_of_mut!(target.header))
-                != 0
-            {
-                bail!(
-                    "failed to get display device configuration information"
+            if result != 0 {
+                debug!(
+                    "failed to get display device configuration information for a display: error {}",
+                    result
                 );
+                continue;
             }
 
             let device_id = string_from_wide(&target.monitorDevicePath);
@@ -116,17 +324,17 @@ fn get_device_id(hmonitor: HMONITOR) -> anyhow::Result<String> {
             .ok()
             .context("failed to get the device name for a display monitor")?;
 
-        let device_name_bytes = slice::from_raw_parts(
+        let device_name_bytes: &[u8] = slice::from_raw_parts(
             monitor_info.szDevice.as_ptr() as _,
             monitor_info.szDevice.len(),
         );
         // The documentation for MONITORINFOEXA doesn't say that the string in
         // szDevice is null-terminated. Because the MONITORINFOEXA struct is
-        // zeroed, it's effectively null-terminated when the name is less than
-        // 32 characters (the size of szDevice). Hopefully device names are
-        // always less than 32 characters...
-        let device_name = CStr::from_bytes_until_nul(device_name_bytes)
-            .expect("display monitor device names should be null-terminated");
+        // zeroed, it's null-terminated when the name is less than 32
+        // characters (the size of szDevice). Otherwise, the name fills the
+        // whole buffer, so copy it and add the terminator.
+        let mut device_name = until_nul(device_name_bytes).to_vec();
+        device_name.push(0);
 
         let mut display_device = DISPLAY_DEVICEA {
             cb: mem::size_of::<DISPLAY_DEVICEA>() as u32,
@@ -134,7 +342,7 @@ fn get_device_id(hmonitor: HMONITOR) -> anyhow::Result<String> {
         };
 
         EnumDisplayDevicesA(
-            PCSTR::from_raw(device_name.as_ptr() as *const u8),
+            PCSTR::from_raw(device_name.as_ptr()),
             0,
             ptr::addr_of_mut!(display_device),
             1,
@@ -147,135 +355,580 @@ fn get_device_id(hmonitor: HMONITOR) -> anyhow::Result<String> {
             display_device.DeviceID.len(),
         );
         // See the comment above about null-terminated strings for szDevice.
-        let device_id = CStr::from_bytes_until_nul(device_id_bytes)
-            .expect("display device IDs should be null-terminated");
-
-        Ok(device_id
-            .to_str()
-            .expect("display device IDs should be valid UTF-8")
-            .to_owned())
+        Ok(string_from_bytes(device_id_bytes))
     }
 }
 
-/// Returns the physical monitor associated with an HMONITOR handle.
+/// Returns the EDID of the display monitor with the device ID `device_id`.
 ///
-/// # Errors
-/// Returns `Err` if there are zero or multiple physical monitors associated
-/// with a handle.
-fn get_physical_monitor(hmonitor: HMONITOR) -> anyhow::Result<HANDLE> {
+/// Windows stores the EDID in the registry under the monitor's device
+/// instance, which is encoded in the device ID, e.g.,
+/// `\\?\DISPLAY#GSM5B08#5&1a2b3c&0&UID4352#{...}` is under
+/// `DISPLAY\GSM5B08\5&1a2b3c&0&UID4352`.
+fn get_edid(device_id: &str) -> anyhow::Result<Vec<u8>> {
+    let parts: Vec<&str> =
+        device_id.trim_start_matches(r"\\?\").split('#').take(3).collect();
+    if parts.len() != 3 {
+        bail!("unexpected device ID format '{}'", device_id);
+    }
+    let subkey = HSTRING::from(format!(
+        r"SYSTEM\CurrentControlSet\Enum\{}\Device Parameters",
+        parts.join(r"\")
+    ));
+
+    unsafe {
+        let mut edid_len: u32 = 0;
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &subkey,
+            w!("EDID"),
+            RRF_RT_REG_BINARY,
+            None,
+            None,
+            Some(ptr::addr_of_mut!(edid_len)),
+        )
+        .ok()
+        .context("failed to get the EDID length")?;
+
+        let mut edid = vec![0; edid_len as usize];
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &subkey,
+            w!("EDID"),
+            RRF_RT_REG_BINARY,
+            None,
+            Some(edid.as_mut_ptr() as _),
+            Some(ptr::addr_of_mut!(edid_len)),
+        )
+        .ok()
+        .context("failed to get the EDID")?;
+        edid.truncate(edid_len as usize);
+
+        Ok(edid)
+    }
+}
+
+/// Returns the number of physical monitors associated with an HMONITOR
+/// handle.
+fn get_physical_monitor_count(hmonitor: HMONITOR) -> anyhow::Result<usize> {
+    let mut num_physical_monitors: u32 = 0;
     unsafe {
-        let mut num_physical_monitors: u32 = 0;
         GetNumberOfPhysicalMonitorsFromHMONITOR(
             hmonitor,
             ptr::addr_of_mut!(num_physical_monitors),
         )
         .context("failed to get the number of physical monitors for a display monitor")?;
+    }
+
+    Ok(num_physical_monitors as usize)
+}
 
+/// Returns the physical monitors associated with an HMONITOR handle.
+///
+/// # Errors
+/// Returns `Err` if there are no physical monitors associated with a handle.
+fn get_physical_monitors(hmonitor: HMONITOR) -> anyhow::Result<Vec<HANDLE>> {
+    unsafe {
+        let num_physical_monitors = get_physical_monitor_count(hmonitor)?;
         if num_physical_monitors == 0 {
             bail!("display monitor has no associated physical monitor");
-        } else if num_physical_monitors > 1 {
-            // I don't know what it means for a display to have multiple
-            // physical monitors. For example, which one would set I VCP codes
-            // on? This is probably a valid scenario, but it's easier to leave
-            // it unhandled for now.
-            bail!(
-                "display monitor has more than one associated physical monitor"
-            );
         }
 
-        let mut physical_monitor = PHYSICAL_MONITOR::default();
-        GetPhysicalMonitorsFromHMONITOR(
-            hmonitor,
-            slice::from_raw_parts_mut(ptr::addr_of_mut!(physical_monitor), 1),
-        )
-        .context("failed to get the physical monitor for a display monitor")?;
+        let mut physical_monitors =
+            vec![PHYSICAL_MONITOR::default(); num_physical_monitors];
+        GetPhysicalMonitorsFromHMONITOR(hmonitor, &mut physical_monitors)
+            .context(
+                "failed to get the physical monitors for a display monitor",
+            )?;
 
-        Ok(physical_monitor.hPhysicalMonitor)
+        Ok(physical_monitors
+            .into_iter()
+            .map(|physical_monitor| physical_monitor.hPhysicalMonitor)
+            .collect())
     }
 }
 
+/// Returns the length of a physical monitor's capabilities string, waiting
+/// until `deadline` for a monitor that reports an empty one.
+fn get_capabilities_string_len(
+    handle: &HANDLE,
+    options: &Options,
+    deadline: Instant,
+) -> anyhow::Result<u32> {
+    loop {
+        // The capabilities functions fail transiently, e.g., right after a
+        // monitor wakes up, so retry them before giving up.
+        let capabilities_string_len = retry(options, || {
+            let mut capabilities_string_len: u32 = 0;
+            BOOL(unsafe {
+                GetCapabilitiesStringLength(
+                    *handle,
+                    ptr::addr_of_mut!(capabilities_string_len),
+                )
+            })
+            .ok()
+            .map_err(ddc_ci_error)
+            .context("failed to get capabilities string length")?;
+
+            Ok(capabilities_string_len)
+        })?;
+
+        if capabilities_string_len > 0 {
+            return Ok(capabilities_string_len);
+        }
+        if Instant::now() >= deadline {
+            bail!("received an empty capabilities string");
+        }
+        thread::sleep(CAPABILITIES_REQUEST_DELAY);
+    }
+}
+
+/// Requests the capabilities string of a physical monitor, or returns `None`
+/// if the reply isn't null-terminated, i.e., it's incomplete.
+fn request_capabilities_string(
+    handle: &HANDLE,
+    capabilities_string_len: u32,
+    options: &Options,
+) -> anyhow::Result<Option<String>> {
+    let mut capabilities_string_bytes =
+        vec![0; capabilities_string_len as usize];
+    retry(options, || {
+        BOOL(unsafe {
+            CapabilitiesRequestAndCapabilitiesReply(
+                *handle,
+                &mut capabilities_string_bytes,
+            )
+        })
+        .ok()
+        .context("failed to get capabilities string")
+    })?;
+
+    let Ok(capabilities_string) =
+        CStr::from_bytes_until_nul(&capabilities_string_bytes)
+    else {
+        return Ok(None);
+    };
+    let capabilities_string = capabilities_string
+        .to_str()
+        .context("capabilities string contains invalid UTF-8")?
+        .to_owned();
+
+    Ok(Some(capabilities_string))
+}
+
+/// Returns the capabilities string of a physical monitor, and where it came
+/// from.
 fn get_capabilities_string(
     device_id: &str,
     handle: &HANDLE,
-) -> anyhow::Result<String> {
-    unsafe {
-        let cache = CapabilitiesCache::new();
-        if let Ok(ref cache) = cache {
-            if let Ok(Some(capabilities_string)) = cache.get(device_id) {
-                return Ok(capabilities_string);
+    options: &Options,
+) -> anyhow::Result<(String, CapabilitiesSource)> {
+    let cache = match options.cache_mode {
+        CacheMode::Enabled | CacheMode::Refresh => {
+            match CapabilitiesCache::new(options.cache_dir.as_deref()) {
+                Ok(cache) => Some(cache),
+                Err(err) => {
+                    debug!("not using the cache: {:#}", err);
+                    None
+                }
             }
         }
+        CacheMode::Disabled => None,
+    };
 
-        let mut capabilities_string_len: u32 = 0;
-        if GetCapabilitiesStringLength(
-            *handle,
-            ptr::addr_of_mut!(capabilities_string_len),
-        ) == FALSE.0
-        {
-            bail!("failed to get capabilities string length");
-        }
+    // Slow monitors, e.g., some TVs, reply with an empty or incomplete
+    // capabilities string while they're still preparing it, so request
+    // it again until it's complete or the capabilities timeout passes.
+    let deadline = Instant::now() + options.capabilities_timeout;
+    let mut capabilities_string_len =
+        get_capabilities_string_len(handle, options, deadline)?;
 
-        // TODO: Add retries for capabilities functions failures. I've seen
-        // transient failures on my machine.
-        if capabilities_string_len == 0 {
-            bail!("received an empty capabilities string");
+    // Getting the length is quick, unlike getting the capabilities
+    // string, and it catches most cases of a different monitor taking
+    // over a cached device ID.
+    if let (Some(cache), CacheMode::Enabled) = (&cache, options.cache_mode) {
+        if let Ok(Some(capabilities_string)) = cache.get(
+            device_id,
+            capabilities_string_len,
+            options.cache_max_age,
+        ) {
+            debug!(
+                "using cached capabilities string for '{}': {}",
+                device_id, capabilities_string
+            );
+            return Ok((capabilities_string, CapabilitiesSource::Cache));
         }
+    }
 
-        let mut capabilities_string_bytes =
-            Vec::with_capacity(capabilities_string_len as usize);
-        if CapabilitiesRequestAndCapabilitiesReply(
-            *handle,
-            slice::from_raw_parts_mut(
-                capabilities_string_bytes.as_mut_ptr(),
-                capabilities_string_len as usize,
+    let capabilities_string = loop {
+        let capabilities_string = request_capabilities_string(
+            handle,
+            capabilities_string_len,
+            options,
+        )?;
+        match capabilities_string {
+            Some(capabilities_string)
+                if parse::is_complete(&capabilities_string) =>
+            {
+                break capabilities_string
+            }
+            _ if Instant::now() >= deadline => bail!(
+                "received an incomplete capabilities string within {} ms",
+                options.capabilities_timeout.as_millis()
             ),
-        ) == FALSE.0
-        {
-            bail!("failed to get capabilities string");
+            _ => {
+                debug!(
+                    "received an incomplete capabilities string for '{}', requesting it again",
+                    device_id
+                );
+                thread::sleep(CAPABILITIES_REQUEST_DELAY);
+                // The length can change once the monitor is ready.
+                capabilities_string_len =
+                    get_capabilities_string_len(handle, options, deadline)?;
+            }
         }
+    };
 
-        capabilities_string_bytes.set_len(capabilities_string_len as usize);
-
-        let capabilities_string =
-            CStr::from_bytes_until_nul(&capabilities_string_bytes)
-                .expect("capabilities strings should be null-terminated")
-                .to_str()
-                .context("capabilities string contains invalid UTF-8")?
-                .to_owned();
+    debug!(
+        "fetched capabilities string for '{}': {}",
+        device_id, capabilities_string
+    );
 
-        if let Ok(ref cache) = cache {
-            let _ = cache.set(device_id, &capabilities_string);
+    // A cache failure, e.g., another chmi process holding the database
+    // for too long, shouldn't keep the monitor from being used.
+    if let Some(cache) = &cache {
+        if let Err(err) =
+            cache.set(device_id, capabilities_string_len, &capabilities_string)
+        {
+            debug!("failed to cache the capabilities string: {:#}", err);
         }
-
-        Ok(capabilities_string)
     }
+
+    Ok((capabilities_string, CapabilitiesSource::Monitor))
 }
 
 pub struct Monitor {
-    handle: HANDLE,
+    /// The physical monitor handle, which is replaced if it goes stale.
+    handle: Cell<HANDLE>,
+    /// The display monitor that the physical monitor belongs to, and the
+    /// index of the physical monitor in it, for reopening the handle.
+    hmonitor: HMONITOR,
+    index: usize,
+    id: String,
     name: String,
+    edid: Option<Edid>,
     capabilities: Capabilities,
+    /// The VCP code to get and set the input with.
+    input_select_code: u8,
+    options: Options,
+    /// The span that DDC/CI requests to the monitor are logged in, so that
+    /// log lines say which monitor they're about.
+    span: Span,
+    /// Whether a DDC/CI request timed out. The abandoned worker thread might
+    /// still be using the handle, so it's leaked instead of destroyed.
+    timed_out: Cell<bool>,
 }
 
+// SAFETY: Display and physical monitor handles aren't tied to the thread
+// that created them, and the monitor isn't shared between threads.
+unsafe impl Send for Monitor {}
+
 impl Monitor {
-    fn new(
+    /// Returns a monitor for each physical monitor associated with an
+    /// HMONITOR handle.
+    fn from_hmonitor(
         hmonitor: HMONITOR,
         friendly_name_map: &HashMap<String, String>,
-    ) -> anyhow::Result<Monitor> {
+        options: &Options,
+    ) -> anyhow::Result<Vec<Monitor>> {
         let device_id = get_device_id(hmonitor)?;
-        let friendly_name = friendly_name_map.get(&device_id).unwrap();
+        Span::current().record("id", &device_id);
+        let friendly_name =
+            friendly_name_map.get(&device_id).with_context(|| {
+                format!("unable to find the name of monitor '{}'", device_id)
+            })?;
+        Span::current().record("name", friendly_name);
 
-        let physical_monitor = get_physical_monitor(hmonitor)?;
+        let physical_monitors = get_physical_monitors(hmonitor)?;
+        let num_physical_monitors = physical_monitors.len();
+        debug!(
+            "found display monitor '{}' ({}) with {} physical monitor(s)",
+            friendly_name, device_id, num_physical_monitors
+        );
 
-        let capabilities_string =
-            get_capabilities_string(&device_id, &physical_monitor)?;
-        let capabilities = parse::parse(&capabilities_string)?;
+        // Physical monitors behind the same display monitor can't be told
+        // apart by their EDID, so it's only used for a lone one.
+        let edid = if num_physical_monitors == 1 {
+            match get_edid(&device_id) {
+                Ok(edid) => Edid::parse(&edid),
+                Err(err) => {
+                    debug!(
+                        "failed to get the EDID of '{}': {:#}",
+                        device_id, err
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-        Ok(Monitor {
-            handle: physical_monitor,
-            name: friendly_name.clone(),
-            capabilities,
-        })
+        let mut monitors = Vec::new();
+        // In strict mode, the first failure is returned, but only after the
+        // loop so that every physical monitor handle is owned and destroyed.
+        let mut strict_failure = None;
+        for (i, physical_monitor) in physical_monitors.into_iter().enumerate()
+        {
+            // A display monitor can have multiple physical monitors, e.g.,
+            // with some docking stations and MST hubs. They share a device
+            // ID, so disambiguate their cache entries by their index.
+            let cache_key = if num_physical_monitors > 1 {
+                format!("{}#{}", device_id, i + 1)
+            } else {
+                device_id.clone()
+            };
+
+            match Monitor::new(
+                physical_monitor,
+                hmonitor,
+                i,
+                friendly_name.clone(),
+                edid.clone(),
+                &cache_key,
+                options,
+            ) {
+                Ok(monitor) => monitors.push(monitor),
+                Err(err) if options.strict => {
+                    strict_failure.get_or_insert_with(|| {
+                        err.context(format!(
+                            "failed to get monitor '{}'",
+                            friendly_name
+                        ))
+                    });
+                }
+                Err(err) if err.is::<TimedOut>() => {
+                    warn!("{:#}, skipping it", err)
+                }
+                Err(err) => {
+                    error!(
+                        "failed to get monitor '{}': {:#}",
+                        friendly_name, err
+                    )
+                }
+            }
+        }
+
+        match strict_failure {
+            Some(err) => Err(err),
+            None => Ok(monitors),
+        }
+    }
+
+    fn new(
+        handle: HANDLE,
+        hmonitor: HMONITOR,
+        index: usize,
+        name: String,
+        edid: Option<Edid>,
+        device_id: &str,
+        options: &Options,
+    ) -> anyhow::Result<Monitor> {
+        // The span outlives the display monitor's span that it's created
+        // in, so don't nest it.
+        let span = info_span!(
+            parent: None,
+            "monitor",
+            name = %name,
+            id = %device_id
+        );
+        let _span = span.enter();
+
+        // Take ownership of the handle up front so that it's destroyed if
+        // getting the capabilities fails.
+        let mut monitor = Monitor {
+            handle: Cell::new(handle),
+            hmonitor,
+            index,
+            id: device_id.to_owned(),
+            name,
+            edid,
+            capabilities: Capabilities::default(),
+            input_select_code: INPUT_SELECT_CODE,
+            options: options.clone(),
+            span: span.clone(),
+            timed_out: Cell::new(false),
+        };
+
+        let (capabilities_string, _) = monitor.fetch_capabilities_string()?;
+        monitor.capabilities = match parse::parse(&capabilities_string) {
+            Ok(capabilities) => capabilities,
+            Err(err) => {
+                // Don't keep an unparsable capabilities string around, e.g.,
+                // a truncated one, so that the next run fetches it again.
+                let cache_dir = monitor.options.cache_dir.as_deref();
+                if let Ok(cache) = CapabilitiesCache::new(cache_dir) {
+                    let _ = cache.invalidate(device_id);
+                }
+                return Err(err);
+            }
+        };
+
+        let model = monitor.capabilities.model.as_deref();
+        let overrides = &monitor.options.overrides;
+        if let Some(input_override) = overrides
+            .iter()
+            .find(|candidate| candidate.matches(device_id, model))
+        {
+            if let Some(code) = input_override.input_select_code {
+                warn!(
+                    "using VCP code {:#04X} from the config file to switch the inputs of monitor '{}'",
+                    code, monitor.name
+                );
+                monitor.input_select_code = code;
+                // The monitor might list the inputs under the code.
+                if let Some(values) = monitor.capabilities.values_for(code) {
+                    let inputs: Vec<Input> = values
+                        .iter()
+                        .map(|&value| Input::from(value))
+                        .collect();
+                    monitor.capabilities.set_inputs(&inputs);
+                }
+            }
+
+            match input_override.inputs() {
+                Ok(inputs) if inputs.is_empty() => {}
+                Ok(inputs) => {
+                    warn!(
+                        "using the inputs from the config file for monitor '{}' instead of its capabilities",
+                        monitor.name
+                    );
+                    monitor.capabilities.set_inputs(&inputs);
+                }
+                Err(err) => warn!(
+                    "ignoring the override for monitor '{}': {:#}",
+                    monitor.name, err
+                ),
+            }
+        }
+
+        // A KVM switch or splitter can answer DDC/CI for the monitors behind
+        // it, so the capabilities can belong to a different monitor. It's a
+        // guess, but a model that isn't in the name, along with no input
+        // select, is a telltale sign.
+        if let Some(model) = &monitor.capabilities.model {
+            let name_has_model = monitor
+                .name
+                .to_ascii_lowercase()
+                .contains(&model.to_ascii_lowercase());
+            if !name_has_model && !monitor.capabilities.has_input_select() {
+                warn!(
+                    "monitor '{}' reports model '{}' and doesn't support input select, there might be a KVM switch or splitter between it and this computer",
+                    monitor.name, model
+                );
+            }
+        }
+
+        Ok(monitor)
+    }
+
+    /// Runs a DDC/CI request with the physical monitor handle, reopening the
+    /// handle and trying once more if the request fails. A handle can go
+    /// stale, e.g., after the monitor sleeps, and then every request fails
+    /// until it's reopened.
+    fn with_reopen<T>(
+        &self,
+        f: impl Fn(HANDLE) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        match f(self.handle.get()) {
+            // A request that timed out might still be using the handle, so
+            // it can't be destroyed.
+            Err(err) if is_os_error(&err) && !self.timed_out.get() => {
+                debug!(
+                    "reopening monitor '{}' after a failed request: {:#}",
+                    self.name, err
+                );
+                if let Err(reopen_err) = self.reopen() {
+                    debug!(
+                        "failed to reopen monitor '{}': {:#}",
+                        self.name, reopen_err
+                    );
+                    return Err(err);
+                }
+                f(self.handle.get())
+            }
+            result => result,
+        }
+    }
+
+    /// Replaces the physical monitor handle with a new one for the same
+    /// physical monitor.
+    fn reopen(&self) -> anyhow::Result<()> {
+        let mut new_handle = None;
+        for (i, handle) in
+            get_physical_monitors(self.hmonitor)?.into_iter().enumerate()
+        {
+            if i == self.index {
+                new_handle = Some(handle);
+            } else {
+                unsafe {
+                    let _ = DestroyPhysicalMonitor(handle);
+                }
+            }
+        }
+        let Some(new_handle) = new_handle else {
+            bail!("monitor '{}' is no longer connected", self.name);
+        };
+
+        let old_handle = self.handle.replace(new_handle);
+        unsafe {
+            let _ = DestroyPhysicalMonitor(old_handle);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the capabilities string on a worker thread. Slow monitors get up
+    /// to the capabilities timeout, which is usually longer than the timeout
+    /// of other requests.
+    fn fetch_capabilities_string(
+        &self,
+    ) -> anyhow::Result<(String, CapabilitiesSource)> {
+        let handle = SendHandle(self.handle.get());
+        let device_id = self.id.clone();
+        let options = self.options.clone();
+        let timeout = options.timeout.max(options.capabilities_timeout);
+        self.run_within(timeout, move || {
+            get_capabilities_string(&device_id, &handle.get(), &options)
+        })?
+    }
+
+    /// Runs a DDC/CI request on a worker thread so that an unresponsive
+    /// monitor can't block the caller for longer than the timeout.
+    fn run_with_timeout<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        self.run_within(self.options.timeout, f)
+    }
+
+    /// Runs a DDC/CI request on a worker thread like
+    /// [`Monitor::run_with_timeout`], but with `timeout`.
+    fn run_within<T: Send + 'static>(
+        &self,
+        timeout: Duration,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        match run_with_timeout(timeout, f) {
+            Ok(value) => Ok(value),
+            // The worker thread panicked, which already printed the panic.
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("DDC/CI request to monitor '{}' failed", self.name)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.timed_out.set(true);
+                Err(TimedOut { name: self.name.clone(), timeout }.into())
+            }
+        }
     }
 }
 
@@ -284,95 +937,149 @@ impl monitor::Monitor for Monitor {
         &self.name
     }
 
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn edid(&self) -> Option<&Edid> {
+        self.edid.as_ref()
+    }
+
     fn capabilities(&self) -> &Capabilities {
         &self.capabilities
     }
 
+    fn input_select_code(&self) -> u8 {
+        self.input_select_code
+    }
+
     fn input(&self) -> anyhow::Result<Input> {
-        let mut value = 0;
-        unsafe {
-            if GetVCPFeatureAndVCPFeatureReply(
-                self.handle,
-                INPUT_SELECT_CODE,
-                None,
-                ptr::addr_of_mut!(value),
-                None,
-            ) == FALSE.0
-            {
-                bail!(
-                    "failed to retrieve the value of VCP code {} for monitor '{}'",
-                    INPUT_SELECT_CODE, self.name
-                );
-            }
-        }
+        let (value, _) = self.vcp(self.input_select_code)?;
 
-        Ok((value as u8)
-            .try_into()
-            .expect("the value of a VCP code should be valid"))
+        Ok(Input::from(cap::input_value(value)))
     }
 
     fn set_input(&mut self, input: Input) -> anyhow::Result<()> {
+        // Monitors tend to ignore inputs they don't have rather than fail,
+        // so check the capabilities first for a useful error.
+        if !self.supports_input(input) {
+            bail!("monitor '{}' doesn't support input {}", self.name, input);
+        }
+
         let value: u8 = input.into();
-        unsafe {
-            // TODO: Use GetLastError to get more error information. Same
-            // thing for GetVCPFeatureAndVCPFeatureReply. See BOOL::ok for
-            // a possible implementation.
-            if SetVCPFeature(self.handle, INPUT_SELECT_CODE, value as u32)
-                == FALSE.0
-            {
-                bail!(
-                    "failed to set VCP code {} to {} for monitor '{}'",
-                    INPUT_SELECT_CODE,
-                    value,
-                    self.name
-                );
+        self.set_input_by_raw(value)?;
+
+        // Some monitors report success but don't actually switch, e.g., when
+        // the new input has no signal. Read the input back to confirm the
+        // change, giving the monitor a moment to report the new value.
+        let mut actual = 0;
+        for _ in 0..SET_INPUT_VERIFY_ATTEMPTS {
+            thread::sleep(SET_INPUT_VERIFY_DELAY);
+            let (vcp_value, _) = self.vcp(self.input_select_code)?;
+            actual = cap::input_value(vcp_value);
+            if actual == value {
+                return Ok(());
             }
         }
 
+        bail!(
+            "monitor '{}' didn't change its input, requested {:#04X} but found {:#04X}",
+            self.name,
+            value,
+            actual
+        );
+    }
+
+    fn vcp_with_type(&self, code: u8) -> anyhow::Result<(VcpType, u16, u16)> {
+        let _span = self.span.enter();
+        let (vcp_type, current, maximum) = self.with_reopen(|handle| {
+            let handle = SendHandle(handle);
+            retry(&self.options, || {
+                self.run_with_timeout(move || unsafe {
+                    let mut vcp_type = MC_VCP_CODE_TYPE::default();
+                    let mut current = 0;
+                    let mut maximum = 0;
+                    // The Monitor Configuration API functions return a
+                    // BOOL-like int and set the last-error code on failure,
+                    // so wrapping the result in BOOL lets BOOL::ok capture
+                    // the error via GetLastError.
+                    BOOL(GetVCPFeatureAndVCPFeatureReply(
+                        handle.get(),
+                        code,
+                        Some(ptr::addr_of_mut!(vcp_type)),
+                        ptr::addr_of_mut!(current),
+                        Some(ptr::addr_of_mut!(maximum)),
+                    ))
+                    .ok()
+                    .map(|()| (vcp_type, current, maximum))
+                })?
+                .map_err(ddc_ci_error)
+                .with_context(|| {
+                    format!(
+                        "failed to retrieve the value of VCP code {} for monitor '{}'",
+                        code, self.name
+                    )
+                })
+            })
+        })?;
+
+        let vcp_type = VcpType::from_raw(vcp_type.0);
+
+        // VCP values are 16 bits wide, so the truncation is lossless.
+        Ok((vcp_type, current as u16, maximum as u16))
+    }
+
+    fn set_vcp(&mut self, code: u8, value: u16) -> anyhow::Result<()> {
+        let _span = self.span.enter();
+        self.with_reopen(|handle| {
+            let handle = SendHandle(handle);
+            retry(&self.options, || {
+                self.run_with_timeout(move || unsafe {
+                    BOOL(SetVCPFeature(handle.get(), code, value as u32)).ok()
+                })?
+                .map_err(ddc_ci_error)
+                .with_context(|| {
+                    format!(
+                        "failed to set VCP code {} to {} for monitor '{}'",
+                        code, value, self.name
+                    )
+                })
+            })
+        })?;
+
         Ok(())
     }
 }
 
 impl Drop for Monitor {
     fn drop(&mut self) {
+        if self.timed_out.get() {
+            return;
+        }
+
         unsafe {
-            let _ = DestroyPhysicalMonitor(self.handle);
+            let _ = DestroyPhysicalMonitor(self.handle.get());
         }
     }
 }
 
-pub fn get_monitors() -> anyhow::Result<Vec<Monitor>> {
-    struct EnumDisplayMonitorContext {
-        monitors: Vec<Monitor>,
-        friendly_name_map: HashMap<String, String>,
-    }
-
+/// Returns the display monitors on the desktop, ordered by their position,
+/// left to right and then top to bottom.
+fn get_hmonitors() -> anyhow::Result<Vec<HMONITOR>> {
     unsafe extern "system" fn enum_display_monitors_callback(
         hmonitor: HMONITOR,
         _: HDC,
-        _: *mut RECT,
+        rect: *mut RECT,
         data: LPARAM,
     ) -> BOOL {
-        let context = &mut *(data.0 as *mut EnumDisplayMonitorContext);
-
-        match Monitor::new(hmonitor, &context.friendly_name_map) {
-            Ok(monitor) => {
-                context.monitors.push(monitor);
-            }
-            Err(err) => {
-                error!("{}: {}", err, err.source().unwrap());
-            }
-        };
+        let hmonitors = &mut *(data.0 as *mut Vec<(HMONITOR, RECT)>);
+        hmonitors.push((hmonitor, *rect));
 
         // Return TRUE to continue the enumeration.
         TRUE
     }
 
-    let mut context = EnumDisplayMonitorContext {
-        monitors: Vec::new(),
-        friendly_name_map: get_friendly_name_map()?,
-    };
-
+    let mut hmonitors: Vec<(HMONITOR, RECT)> = Vec::new();
     unsafe {
         // Pass None, i.e., NULL, for the first two parameters to enumerate
         // all display monitors.
@@ -380,11 +1087,374 @@ pub fn get_monitors() -> anyhow::Result<Vec<Monitor>> {
             None,
             None,
             Some(enum_display_monitors_callback),
-            LPARAM(ptr::addr_of_mut!(context) as _),
+            LPARAM(ptr::addr_of_mut!(hmonitors) as _),
         )
         .ok()
         .context("failed to enumerate display monitors")?;
     }
 
-    Ok(context.monitors)
+    // The enumeration order can change across reboots and display changes,
+    // so order the monitors by their position on the desktop, left to right
+    // and then top to bottom, to keep indices stable.
+    hmonitors.sort_by_key(|(_, rect)| (rect.left, rect.top));
+
+    Ok(hmonitors.into_iter().map(|(hmonitor, _)| hmonitor).collect())
+}
+
+pub fn get_monitors(options: &Options) -> anyhow::Result<Vec<Monitor>> {
+    let hmonitors = get_hmonitors()?;
+    let friendly_name_map = get_friendly_name_map()?;
+
+    // Getting capabilities strings is slow and independent per monitor, so
+    // get the monitors for each display monitor in parallel. Joining the
+    // threads in order keeps the order of the monitors stable.
+    let monitors: anyhow::Result<Vec<Vec<Monitor>>> = thread::scope(|scope| {
+        let threads: Vec<_> = hmonitors
+            .into_iter()
+            .map(|hmonitor| {
+                // The device ID and name are recorded once they're known, so
+                // that a display monitor that fails early can still be told
+                // apart by its handle.
+                let span = display_span(hmonitor);
+                let hmonitor = SendHandle(hmonitor);
+                let friendly_name_map = &friendly_name_map;
+                let thread = scope.spawn({
+                    let span = span.clone();
+                    move || {
+                        let _span = span.enter();
+                        Monitor::from_hmonitor(
+                            hmonitor.get(),
+                            friendly_name_map,
+                            options,
+                        )
+                    }
+                });
+                (span, thread)
+            })
+            .collect();
+
+        threads
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, (span, thread))| {
+                let _span = span.enter();
+                match thread
+                    .join()
+                    .unwrap_or_else(|err| panic::resume_unwind(err))
+                {
+                    Ok(monitors) => Some(Ok(monitors)),
+                    Err(err) if options.strict => Some(Err(err.context(
+                        format!("failed to get display monitor {}", i + 1),
+                    ))),
+                    Err(err) => {
+                        error!(
+                            "failed to get display monitor {}: {:#}",
+                            i + 1,
+                            err
+                        );
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+    let mut monitors: Vec<Monitor> = monitors?.into_iter().flatten().collect();
+
+    // Monitors can share a friendly name, e.g., two monitors of the same
+    // model. Append a number to the names of all but the first monitor with
+    // a name so that names are unique.
+    let mut name_counts: HashMap<String, usize> = HashMap::new();
+    for monitor in &mut monitors {
+        let count = name_counts.entry(monitor.name.clone()).or_default();
+        *count += 1;
+        if *count > 1 {
+            monitor.name = format!("{} #{}", monitor.name, count);
+        }
+    }
+
+    Ok(monitors)
+}
+
+/// Diagnoses each display monitor and the physical monitors behind it, for
+/// `chmi doctor`. Unlike [`get_monitors`], failures are part of the diagnosis
+/// rather than skipped, and every step is tried regardless.
+pub fn diagnose(options: &Options) -> anyhow::Result<Vec<DisplayDiagnosis>> {
+    let hmonitors = get_hmonitors()?;
+    let friendly_name_map =
+        get_friendly_name_map().map_err(|err| format!("{:#}", err));
+
+    Ok(hmonitors
+        .into_iter()
+        .map(|hmonitor| {
+            let _span = display_span(hmonitor).entered();
+            diagnose_display(hmonitor, &friendly_name_map, options)
+        })
+        .collect())
+}
+
+fn diagnose_display(
+    hmonitor: HMONITOR,
+    friendly_name_map: &Check<HashMap<String, String>>,
+    options: &Options,
+) -> DisplayDiagnosis {
+    let id = get_device_id(hmonitor).map_err(|err| format!("{:#}", err));
+    let name = match (&id, friendly_name_map) {
+        (Ok(id), Ok(friendly_name_map)) => friendly_name_map
+            .get(id)
+            .cloned()
+            .ok_or_else(|| "unable to find the name".to_owned()),
+        (Err(_), _) => {
+            Err("unable to find the name without the ID".to_owned())
+        }
+        (_, Err(err)) => Err(err.clone()),
+    };
+    let physical_monitor_count = get_physical_monitor_count(hmonitor)
+        .map_err(|err| format!("{:#}", err));
+
+    // Fall back to something for the messages of failed requests.
+    let monitor_name = name
+        .clone()
+        .or_else(|_| id.clone())
+        .unwrap_or_else(|_| "unknown".to_owned());
+    let device_id = id.clone().unwrap_or_default();
+    let monitors = get_physical_monitors(hmonitor)
+        .map(|physical_monitors| {
+            let num_physical_monitors = physical_monitors.len();
+            physical_monitors
+                .into_iter()
+                .enumerate()
+                .map(|(i, handle)| {
+                    let cache_key = if num_physical_monitors > 1 {
+                        format!("{}#{}", device_id, i + 1)
+                    } else {
+                        device_id.clone()
+                    };
+                    let monitor = Monitor {
+                        handle: Cell::new(handle),
+                        hmonitor,
+                        index: i,
+                        id: cache_key,
+                        name: monitor_name.clone(),
+                        edid: None,
+                        capabilities: Capabilities::default(),
+                        input_select_code: INPUT_SELECT_CODE,
+                        options: options.clone(),
+                        span: info_span!(
+                            parent: None,
+                            "monitor",
+                            name = %monitor_name,
+                            id = %device_id
+                        ),
+                        timed_out: Cell::new(false),
+                    };
+                    diagnose_monitor(&monitor)
+                })
+                .collect()
+        })
+        .map_err(|err| format!("{:#}", err));
+
+    DisplayDiagnosis { id, name, physical_monitor_count, monitors }
+}
+
+fn diagnose_monitor(monitor: &Monitor) -> MonitorDiagnosis {
+    let _span = monitor.span.enter();
+
+    let capabilities_string = monitor
+        .fetch_capabilities_string()
+        .map_err(|err| format!("{:#}", err));
+    let capabilities =
+        capabilities_string.as_ref().ok().map(|(capabilities_string, _)| {
+            parse::parse(capabilities_string)
+                .map_err(|err| format!("{:#}", err))
+        });
+
+    // The input is read even if the capabilities are missing, since some
+    // monitors support input select without listing it.
+    let input = monitor::Monitor::vcp(monitor, INPUT_SELECT_CODE)
+        .map(|(value, _)| cap::input_value(value))
+        .map_err(|err| format!("{:#}", err));
+
+    MonitorDiagnosis { capabilities_string, capabilities, input }
+}
+
+/// Returns the span that a display monitor's log lines are logged in.
+fn display_span(hmonitor: HMONITOR) -> Span {
+    info_span!(
+        "display",
+        hmonitor = ?hmonitor.0,
+        id = field::Empty,
+        name = field::Empty
+    )
+}
+
+/// The app ID that notifications are shown under. Toast notifications need
+/// the ID of an installed app, and chmi isn't one, so borrow PowerShell's.
+#[cfg(feature = "notify")]
+const NOTIFICATION_APP_ID: &str = r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+
+/// Shows `message` in a toast notification.
+#[cfg(feature = "notify")]
+pub fn show_notification(message: &str) -> anyhow::Result<()> {
+    use windows::{
+        Data::Xml::Dom::XmlDocument,
+        UI::Notifications::{ToastNotification, ToastNotificationManager},
+    };
+
+    let escaped: String = message
+        .chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            c => c.to_string(),
+        })
+        .collect();
+    let xml = format!(
+        r#"<toast><visual><binding template="ToastGeneric"><text>chmi</text><text>{}</text></binding></visual></toast>"#,
+        escaped
+    );
+
+    let document = XmlDocument::new()?;
+    document.LoadXml(&HSTRING::from(xml))?;
+    let notification = ToastNotification::CreateToastNotification(&document)
+        .context("failed to create a notification")?;
+    ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(
+        NOTIFICATION_APP_ID,
+    ))?
+    .Show(&notification)
+    .context("failed to show a notification")
+}
+
+/// Returns the display monitor that the console window is on.
+fn get_console_hmonitor() -> anyhow::Result<HMONITOR> {
+    let hwnd = unsafe { GetConsoleWindow() };
+    if hwnd.is_invalid() {
+        bail!("unable to find the console window, is chmi running in a terminal?");
+    }
+
+    Ok(unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) })
+}
+
+/// Returns whether the console window is on the display monitor with the
+/// device ID `device_id`.
+pub fn is_console_on(device_id: &str) -> anyhow::Result<bool> {
+    let console_device_id = get_device_id(get_console_hmonitor()?)?;
+    // Physical monitors behind the same display monitor have their index
+    // appended to the device ID. See `Monitor::from_hmonitor`.
+    Ok(device_id == console_device_id
+        || device_id
+            .strip_prefix(&console_device_id)
+            .is_some_and(|index| index.starts_with('#')))
+}
+
+/// Returns the monitor that the console window is on.
+pub fn get_current_monitor(options: &Options) -> anyhow::Result<Monitor> {
+    let hmonitor = get_console_hmonitor()?;
+    let _span = display_span(hmonitor).entered();
+    let monitors =
+        Monitor::from_hmonitor(hmonitor, &get_friendly_name_map()?, options)?;
+
+    // If the display monitor has multiple physical monitors, there's no way
+    // to tell which one the window is on, so use the first one.
+    monitors
+        .into_iter()
+        .next()
+        .context("unable to get the monitor the console window is on")
+}
+
+/// Returns the number of display monitors on the desktop, including ones
+/// that don't respond to DDC/CI.
+pub fn display_count() -> usize {
+    unsafe { GetSystemMetrics(SM_CMONITORS) as usize }
+}
+
+/// Calls `on_change` whenever the displays change, e.g., when a monitor is
+/// connected or disconnected. Only returns if getting window messages fails.
+pub fn watch_display_changes(
+    mut on_change: impl FnMut(),
+) -> anyhow::Result<()> {
+    unsafe extern "system" fn window_proc(
+        hwnd: HWND,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        // These messages are sent rather than posted, so they bypass the
+        // message loop. Post a message to handle the change there instead.
+        if msg == WM_DISPLAYCHANGE || msg == WM_DEVICECHANGE {
+            let _ = PostMessageW(
+                hwnd,
+                DISPLAY_CHANGE_MESSAGE,
+                WPARAM(0),
+                LPARAM(0),
+            );
+        }
+
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    unsafe {
+        let instance: HINSTANCE = GetModuleHandleW(None)
+            .context("failed to get the module handle")?
+            .into();
+
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: w!("chmi"),
+            ..WNDCLASSW::default()
+        };
+        if RegisterClassW(ptr::addr_of!(class)) == 0 {
+            return Err(windows::core::Error::from_win32())
+                .context("failed to register the window class");
+        }
+
+        // Message-only windows don't receive broadcast messages like
+        // WM_DISPLAYCHANGE, so create a top-level window that's never shown.
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            w!("chmi"),
+            w!("chmi"),
+            WS_OVERLAPPED,
+            0,
+            0,
+            0,
+            0,
+            None,
+            None,
+            instance,
+            None,
+        )
+        .context("failed to create a window for display changes")?;
+
+        let mut msg = MSG::default();
+        loop {
+            match GetMessageW(ptr::addr_of_mut!(msg), None, 0, 0).0 {
+                -1 => {
+                    return Err(windows::core::Error::from_win32())
+                        .context("failed to get a window message")
+                }
+                0 => return Ok(()),
+                _ => {}
+            }
+
+            if msg.message == DISPLAY_CHANGE_MESSAGE {
+                thread::sleep(DISPLAY_CHANGE_DELAY);
+                // Handle a burst of changes once.
+                while PeekMessageW(
+                    ptr::addr_of_mut!(msg),
+                    hwnd,
+                    DISPLAY_CHANGE_MESSAGE,
+                    DISPLAY_CHANGE_MESSAGE,
+                    PM_REMOVE,
+                )
+                .as_bool()
+                {}
+                on_change();
+            } else {
+                let _ = TranslateMessage(ptr::addr_of!(msg));
+                DispatchMessageW(ptr::addr_of!(msg));
+            }
+        }
+    }
 }